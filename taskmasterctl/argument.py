@@ -5,6 +5,7 @@ import signal
 class Argument(Enum):
     HTTP = auto()
     MAINTAIL = auto()
+    NAME_AND_INDEX = auto()
     ONE = auto()
     OPTIONAL_POSITIVE = auto()
     OPTIONAL_STRING = auto()
@@ -17,16 +18,18 @@ class Argument(Enum):
 CHECK_ARGC = {
     Argument.HTTP: lambda argc: 1 <= argc <= 2,
     Argument.MAINTAIL: lambda argc: argc <= 1,
+    Argument.NAME_AND_INDEX: lambda argc: 1 <= argc <= 2,
     Argument.ONE: lambda argc: argc == 1,
     Argument.OPTIONAL_POSITIVE: lambda argc: argc <= 1,
     Argument.OPTIONAL_STRING: lambda argc: argc <= 1,
     Argument.SIGNAL: lambda argc: 2 <= argc <= 3,
-    Argument.TAIL: lambda argc: 2 <= argc <= 3,
+    Argument.TAIL: lambda argc: 2 <= argc <= 4,
     Argument.ZERO: lambda argc: argc == 0,
     Argument.ZERO_TO_TWO: lambda argc: argc <= 2,
 }
 
 ARGUMENT_STRING = {
+    Argument.NAME_AND_INDEX: "requires a task name and an optional index",
     Argument.ONE: "requires exactly one argument",
     Argument.OPTIONAL_POSITIVE: "accepts zero or one unsigned integer argument",
     Argument.OPTIONAL_STRING: "accepts zero or one argument",
@@ -110,6 +113,13 @@ def format_arguments_maintail(command, argc, argv):
         return {command: tail_type}
 
 
+def format_arguments_name_and_index(command, argc, argv):
+    if argc == 1:
+        return {command: [argv[0], None]}
+    idx = parse_index(argv[1])
+    return None if idx is None else {command: [argv[0], idx]}
+
+
 def format_arguments_one(command, argc, argv):
     return {command: argv[0]}
 
@@ -162,14 +172,25 @@ def format_arguments_signal(command, argc, argv):
 
 def format_arguments_tail(command, argc, argv):
     task_name = argv[0]
+    idx = None
+    if argv[1].isdigit():
+        idx = parse_index(argv[1])
+        argv = argv[:1] + argv[2:]
+        argc -= 1
+        if argc < 2:
+            print("tail requires stdout or stderr")
+            return None
     output_type = argv[1].title()
     if output_type != "Stdout" and output_type != "Stderr":
         print(f'Invalid output type: "{argv[1]}"')
         return None
+    if argc > 3:
+        print(f'Unexpected argument: "{argv[3]}"')
+        return None
     tail_type = get_tail_type("" if argc == 2 else argv[2])
     if tail_type is None:
         return None
-    return {command: [task_name, output_type, tail_type]}
+    return {command: [task_name, idx, output_type, tail_type]}
 
 
 def format_arguments_zero(command, argc, argv):
@@ -189,6 +210,7 @@ def format_arguments_zero_to_two(command, argc, argv):
 FORMAT_ARGUMENTS = {
     Argument.HTTP: format_arguments_http,
     Argument.MAINTAIL: format_arguments_maintail,
+    Argument.NAME_AND_INDEX: format_arguments_name_and_index,
     Argument.ONE: format_arguments_one,
     Argument.OPTIONAL_POSITIVE: format_arguments_optional_positive,
     Argument.OPTIONAL_STRING: format_arguments_optional_string,
@@ -1,3 +1,4 @@
+import argparse
 import cmd
 import glob
 import inspect
@@ -5,6 +6,7 @@ import json
 import os
 import socket
 import readline
+import struct
 
 from argument import (
     Argument,
@@ -13,9 +15,10 @@ from argument import (
     get_argument_string,
 )
 
-BUFFER_SIZE = 1024
+FRAME_HEADER_SIZE = 4
+PROTOCOL_VERSION = 1
 INTRO_CHAR = "="
-UNIX_DOMAIN_SOCKET_PATH = "/tmp/taskmaster.sock"
+UNIX_DOMAIN_SOCKET_PATH = os.environ.get("TASKMASTER_SOCKET", "/tmp/taskmaster.sock")
 
 RESET = "\033[0m"
 BOLD = "\033[1m"
@@ -25,8 +28,42 @@ CYAN = "\033[96m"
 PROMPT_START_IGNORE = "\001"
 PROMPT_END_IGNORE = "\002"
 
+request_id = 0
+response_format = "text"
+
+
+def recv_exact(s, size):
+    data = b""
+    while len(data) < size:
+        part = s.recv(size - len(data))
+        if not part:
+            return None
+        data += part
+    return data
+
+
+def read_response(s):
+    header = recv_exact(s, FRAME_HEADER_SIZE)
+    if header is None:
+        return None
+    (length,) = struct.unpack(">I", header)
+    frame = recv_exact(s, length)
+    if frame is None:
+        return None
+    return json.loads(frame.decode())
+
 
 def communicate(message):
+    global request_id
+    request_id += 1
+    request = json.dumps(
+        {
+            "version": PROTOCOL_VERSION,
+            "id": request_id,
+            "action": message,
+            "format": response_format,
+        }
+    ).encode()
     try:
         with socket.socket(socket.AF_UNIX, socket.SOCK_STREAM) as s:
             try:
@@ -38,31 +75,38 @@ def communicate(message):
                 print(f"Failed to connect to taskmasterd: {e}")
                 return
             try:
-                s.sendall(message.encode())
+                s.sendall(struct.pack(">I", len(request)) + request)
             except Exception as e:
                 print(f"Failed to write to taskmasterd: {e}")
                 return
             needs_newline = False
             while True:
                 try:
-                    part = s.recv(BUFFER_SIZE).decode()
+                    response = read_response(s)
                 except KeyboardInterrupt:
                     print()
                     return
                 except Exception as e:
                     print(f"Failed to read from taskmasterd: {e}")
                     return
-                if not part:
+                if response is None:
+                    if message == "Shutdown":
+                        print("Shutdown successful")
                     break
-                print(part, flush=True, end="")
-                if not part.endswith("\n"):
-                    needs_newline = True
+                if response["id"] != request_id:
+                    print(f"Unexpected response id {response['id']}")
+                    return
+                payload = response["payload"]
+                if "result" in payload:
+                    part = json.dumps(payload["result"], indent=2)
                 else:
-                    needs_newline = False
+                    part = payload["message"]
+                print(part, flush=True, end="")
+                needs_newline = bool(part) and not part.endswith("\n")
+                if not response["more"]:
+                    break
             if needs_newline:
                 print()
-            elif message == '"Shutdown"':
-                print("Shutdown successful")
     except Exception as e:
         print(f"Unknown error: {e}")
 
@@ -77,7 +121,7 @@ def process_cmd(arg, expected_argument):
     if CHECK_ARGC[expected_argument](argc):
         message = FORMAT_ARGUMENTS[expected_argument](command.title(), argc, argv)
         if message is not None:
-            communicate(json.dumps(message))
+            communicate(message)
     else:
         print(f"{command} {get_argument_string(expected_argument)}")
         class_name = calling_frame.f_locals["self"].__class__.__name__
@@ -130,13 +174,25 @@ class TaskMasterShell(cmd.Cmd):
     do_quit = do_exit
 
     def do_clear(self, arg):
-        """clear <name> : Clear the log files of a process"""
-        process_cmd(arg, Argument.ONE)
+        """clear <name>       : Clear the log files of every process of a task\nclear <name> <idx> : Clear the log files of a process"""
+        process_cmd(arg, Argument.NAME_AND_INDEX)
 
     def do_config(self, arg):
         """config <name> : Get the task configuration in json"""
         process_cmd(arg, Argument.ONE)
 
+    def do_format(self, arg):
+        """format      : Show the current output format\nformat text : Show human readable answers\nformat json : Show answers as json results"""
+        global response_format
+        argv = arg.split()
+        if not argv:
+            print(f"format: {response_format}")
+        elif len(argv) == 1 and argv[0] in ("text", "json"):
+            response_format = argv[0]
+        else:
+            print("format <text or json>")
+            print(self.do_format.__doc__)
+
     def do_http(self, arg):
         """http enable <port> : Enable http logging\nhttp disable       : Disable http logging\nhttp status        : Show http logging status"""
         process_cmd(arg, Argument.HTTP)
@@ -170,7 +226,7 @@ class TaskMasterShell(cmd.Cmd):
         process_cmd(arg, Argument.OPTIONAL_STRING)
 
     def do_tail(self, arg):
-        """tail <taskname> <stdout or stderr>     : complete taskmasterd main log file\ntail <taskname> <stdout or stderr> N   : last N lines of taskmasterd main log file\ntail <taskname> <stdout or stderr> f   : complete and continuous taskmasterd main log file\ntail <taskname> <stdout or stderr> f42 : last N lines of taskmasterd main log file, continuously"""
+        """tail <taskname> <stdout or stderr>       : complete log file of the first process\ntail <taskname> <idx> <stdout or stderr> : complete log file of process idx\ntail <taskname> <stdout or stderr> N     : last N lines of the log file\ntail <taskname> <stdout or stderr> f     : complete and continuous log file\ntail <taskname> <stdout or stderr> f42   : last N lines of the log file, continuously"""
         process_cmd(arg, Argument.TAIL)
 
     def do_update(self, arg):
@@ -187,6 +243,14 @@ class TaskMasterShell(cmd.Cmd):
 
 
 if __name__ == "__main__":
+    parser = argparse.ArgumentParser(description="taskmasterd control shell")
+    parser.add_argument(
+        "-s",
+        "--socket",
+        default=UNIX_DOMAIN_SOCKET_PATH,
+        help=f"taskmasterd socket path (default {UNIX_DOMAIN_SOCKET_PATH})",
+    )
+    UNIX_DOMAIN_SOCKET_PATH = parser.parse_args().socket
     width = os.get_terminal_size().columns
     top_line = INTRO_CHAR * width
     middle_line = "  WELCOME TO TASKMASTER  ".center(width, INTRO_CHAR)
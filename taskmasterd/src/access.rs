@@ -0,0 +1,327 @@
+use crate::action::{Action, Target, ACTION_NAMES};
+use crate::identity::{lookup_group, lookup_user, user_groups, user_name};
+use crate::utils::wildcard_match;
+use libc::{gid_t, pid_t, uid_t};
+use serde::Deserialize;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+const EVERYONE: &str = "*";
+const READ_ONLY_ACTIONS: [&str; 3] = ["status", "tail", "maintail"];
+
+#[derive(Debug, Clone, Copy)]
+pub struct Peer {
+    pub pid: pid_t,
+    pub uid: uid_t,
+    pub gid: gid_t,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AccessRule {
+    #[serde(default)]
+    pub users: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    pub actions: Vec<String>,
+    #[serde(default = "all_tasks")]
+    pub tasks: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    everyone: bool,
+    uids: Vec<uid_t>,
+    gids: Vec<gid_t>,
+    actions: Vec<String>,
+    tasks: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Access {
+    owner: uid_t,
+    rules: Vec<Rule>,
+}
+
+fn all_tasks() -> Vec<String> {
+    vec![EVERYONE.to_string()]
+}
+
+impl Peer {
+    pub fn from_stream(stream: &UnixStream) -> io::Result<Peer> {
+        let mut credentials: libc::ucred = unsafe { std::mem::zeroed() };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Peer {
+            pid: credentials.pid,
+            uid: credentials.uid,
+            gid: credentials.gid,
+        })
+    }
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match user_name(self.uid) {
+            Some(name) => write!(f, "{name} (UID {}, PID {})", self.uid, self.pid),
+            None => write!(f, "UID {} (PID {})", self.uid, self.pid),
+        }
+    }
+}
+
+impl Rule {
+    fn resolve(rule: &AccessRule) -> Result<Rule, String> {
+        for action in &rule.actions {
+            if action != EVERYONE && !ACTION_NAMES.contains(&action.as_str()) {
+                return Err(format!(
+                    "unknown action \"{action}\", expected one of: {}",
+                    ACTION_NAMES.join(", ")
+                ));
+            }
+        }
+        if rule.users.is_empty() && rule.groups.is_empty() {
+            return Err("a rule needs at least one of users or groups".to_string());
+        }
+        let mut resolved = Rule {
+            everyone: false,
+            uids: Vec::new(),
+            gids: Vec::new(),
+            actions: rule.actions.clone(),
+            tasks: rule.tasks.clone(),
+        };
+        for user in &rule.users {
+            if user == EVERYONE {
+                resolved.everyone = true;
+            } else {
+                resolved.uids.push(lookup_user(user)?.0);
+            }
+        }
+        for group in &rule.groups {
+            resolved.gids.push(lookup_group(group)?);
+        }
+        Ok(resolved)
+    }
+
+    fn applies_to(&self, peer: &Peer, groups: &[gid_t]) -> bool {
+        self.everyone
+            || self.uids.contains(&peer.uid)
+            || groups.iter().any(|gid| self.gids.contains(gid))
+    }
+
+    fn allows_action(&self, action: &str) -> bool {
+        self.actions
+            .iter()
+            .any(|allowed| allowed == EVERYONE || allowed == action)
+    }
+
+    fn allows_task(&self, task_name: &str) -> bool {
+        self.tasks
+            .iter()
+            .any(|pattern| wildcard_match(pattern, task_name))
+    }
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Access {
+            owner: unsafe { libc::geteuid() },
+            rules: vec![Rule {
+                everyone: true,
+                uids: Vec::new(),
+                gids: Vec::new(),
+                actions: READ_ONLY_ACTIONS.iter().map(|a| a.to_string()).collect(),
+                tasks: all_tasks(),
+            }],
+        }
+    }
+}
+
+impl Access {
+    pub fn new(rules: &[AccessRule]) -> Result<Access, String> {
+        Ok(Access {
+            owner: unsafe { libc::geteuid() },
+            rules: rules.iter().map(Rule::resolve).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn authorize(
+        &self,
+        peer: &Peer,
+        action: &Action,
+        task_names: &[String],
+    ) -> Result<(), String> {
+        if peer.uid == 0 || peer.uid == self.owner {
+            return Ok(());
+        }
+        if let Action::Update(Some(_)) = action {
+            return Err(format!(
+                "Permission denied: {peer} is not allowed to load another configuration file"
+            ));
+        }
+        let mut groups = user_groups(peer.uid, peer.gid);
+        groups.push(peer.gid);
+        let rules: Vec<&Rule> = self
+            .rules
+            .iter()
+            .filter(|rule| rule.applies_to(peer, &groups) && rule.allows_action(action.name()))
+            .collect();
+        let allowed = match action.target() {
+            Target::Daemon => !rules.is_empty(),
+            Target::Task(task_name) => rules.iter().any(|rule| rule.allows_task(task_name)),
+            Target::AllTasks => task_names
+                .iter()
+                .all(|task_name| rules.iter().any(|rule| rule.allows_task(task_name))),
+        };
+        if allowed {
+            return Ok(());
+        }
+        Err(match action.target() {
+            Target::Task(task_name) => format!(
+                "Permission denied: {peer} is not allowed to {} {task_name}",
+                action.name()
+            ),
+            _ => format!(
+                "Permission denied: {peer} is not allowed to {}",
+                action.name()
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::{OutputType, TailType};
+
+    const OWNER: uid_t = 4000;
+    const OPERATOR: uid_t = 4001;
+    const STRANGER: uid_t = 4002;
+    const OPERATORS: gid_t = 4100;
+
+    fn peer(uid: uid_t, gid: gid_t) -> Peer {
+        Peer { pid: 1, uid, gid }
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    fn rule(uids: &[uid_t], gids: &[gid_t], actions: &[&str], tasks: &[&str]) -> Rule {
+        Rule {
+            everyone: false,
+            uids: uids.to_vec(),
+            gids: gids.to_vec(),
+            actions: strings(actions),
+            tasks: strings(tasks),
+        }
+    }
+
+    fn start(name: &str) -> Action {
+        Action::Start(Some((name.to_string(), None)))
+    }
+
+    #[test]
+    fn authorizes_by_rules() {
+        let access = Access {
+            owner: OWNER,
+            rules: vec![
+                rule(&[OPERATOR], &[], &["start", "stop"], &["web-*"]),
+                rule(&[], &[OPERATORS], &["restart"], &["*"]),
+                rule(&[STRANGER], &[], &["*"], &["db"]),
+            ],
+        };
+        let task_names = strings(&["web-1", "web-2"]);
+        let cases = [
+            (
+                peer(0, 0),
+                Action::Update(Some("/etc/other.yml".to_string())),
+                true,
+            ),
+            (peer(OWNER, 1), Action::Shutdown, true),
+            (peer(OPERATOR, 1), start("web-1"), true),
+            (peer(OPERATOR, 1), start("db"), false),
+            (peer(OPERATOR, 1), Action::Restart(None), false),
+            (peer(OPERATOR, 1), Action::Stop(None), true),
+            (peer(STRANGER, 1), Action::Stop(None), false),
+            (peer(STRANGER, 1), Action::Config("db".to_string()), true),
+            (peer(STRANGER, 1), Action::Shutdown, true),
+            (peer(STRANGER, OPERATORS), Action::Restart(None), true),
+            (peer(STRANGER, OPERATORS), start("web-1"), false),
+            (peer(STRANGER, 1), Action::Update(None), true),
+            (
+                peer(STRANGER, 1),
+                Action::Update(Some("/tmp/x.yml".to_string())),
+                false,
+            ),
+        ];
+        for (i, (peer, action, expected)) in cases.iter().enumerate() {
+            let result = access.authorize(peer, action, &task_names);
+            assert_eq!(result.is_ok(), *expected, "case {i}: {result:?}");
+        }
+    }
+
+    #[test]
+    fn allows_only_reading_by_default() {
+        let access = Access {
+            owner: OWNER,
+            ..Access::default()
+        };
+        let stranger = peer(STRANGER, 1);
+        let task_names = strings(&["web"]);
+        let tail = Action::Tail(
+            "web".to_string(),
+            None,
+            OutputType::Stdout,
+            TailType::Fixed(None),
+        );
+        for action in [
+            Action::Status(None),
+            tail,
+            Action::Maintail(TailType::Fixed(None)),
+        ] {
+            assert!(access.authorize(&stranger, &action, &task_names).is_ok());
+        }
+        for action in [
+            Action::Config("web".to_string()),
+            start("web"),
+            Action::Update(None),
+            Action::Shutdown,
+        ] {
+            assert!(access.authorize(&stranger, &action, &task_names).is_err());
+        }
+        assert_eq!(
+            access
+                .authorize(&stranger, &start("web"), &task_names)
+                .unwrap_err(),
+            "Permission denied: UID 4002 (PID 1) is not allowed to start web"
+        );
+    }
+
+    #[test]
+    fn resolves_rules() {
+        let rule = |users: &[&str], actions: &[&str]| AccessRule {
+            users: strings(users),
+            groups: Vec::new(),
+            actions: strings(actions),
+            tasks: all_tasks(),
+        };
+        let access = Access::new(&[rule(&["nobody", "*"], &["stop"])]).unwrap();
+        assert_eq!(access.rules[0].uids, [65534]);
+        assert!(access.rules[0].everyone);
+        assert!(Access::new(&[rule(&["nobody"], &["dance"])]).is_err());
+        assert!(Access::new(&[rule(&["no-such-user"], &["stop"])]).is_err());
+        assert!(Access::new(&[rule(&[], &["stop"])]).is_err());
+    }
+}
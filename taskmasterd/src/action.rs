@@ -1,4 +1,4 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::fmt::{Display, Formatter};
 
 #[derive(Eq, PartialEq, Serialize, Deserialize, Clone)]
@@ -24,7 +24,8 @@ impl Display for OutputType {
 
 #[derive(Eq, PartialEq, Serialize, Deserialize, Clone)]
 pub enum Action {
-    Clear(String),
+    #[serde(deserialize_with = "deserialize_clear")]
+    Clear(String, Option<usize>),
     Config(String),
     HttpLogging(Option<u16>),
     GetHttpLoggingStatus,
@@ -35,6 +36,138 @@ pub enum Action {
     Start(Option<(String, Option<usize>)>),
     Status(Option<String>),
     Stop(Option<(String, Option<usize>)>),
-    Tail(String, OutputType, TailType),
+    #[serde(deserialize_with = "deserialize_tail")]
+    Tail(String, Option<usize>, OutputType, TailType),
     Update(Option<String>),
 }
+
+pub const ACTION_NAMES: [&str; 12] = [
+    "clear", "config", "http", "maintail", "shutdown", "restart", "signal", "start", "status",
+    "stop", "tail", "update",
+];
+
+fn deserialize_clear<'de, D>(deserializer: D) -> Result<(String, Option<usize>), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Clear {
+        Indexed(String, Option<usize>),
+        Legacy(String),
+    }
+
+    Ok(match Clear::deserialize(deserializer)? {
+        Clear::Indexed(name, index) => (name, index),
+        Clear::Legacy(name) => (name, None),
+    })
+}
+
+fn deserialize_tail<'de, D>(
+    deserializer: D,
+) -> Result<(String, Option<usize>, OutputType, TailType), D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Tail {
+        Indexed(String, Option<usize>, OutputType, TailType),
+        Legacy(String, OutputType, TailType),
+    }
+
+    Ok(match Tail::deserialize(deserializer)? {
+        Tail::Indexed(name, index, output, tail) => (name, index, output, tail),
+        Tail::Legacy(name, output, tail) => (name, None, output, tail),
+    })
+}
+
+pub enum Target<'a> {
+    Daemon,
+    Task(&'a str),
+    AllTasks,
+}
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Clear(_, _) => "clear",
+            Action::Config(_) => "config",
+            Action::HttpLogging(_) | Action::GetHttpLoggingStatus => "http",
+            Action::Maintail(_) => "maintail",
+            Action::Shutdown => "shutdown",
+            Action::Restart(_) => "restart",
+            Action::Signal(_, _, _) => "signal",
+            Action::Start(_) => "start",
+            Action::Status(_) => "status",
+            Action::Stop(_) => "stop",
+            Action::Tail(_, _, _, _) => "tail",
+            Action::Update(_) => "update",
+        }
+    }
+
+    pub fn target(&self) -> Target<'_> {
+        match self {
+            Action::Clear(name, _)
+            | Action::Config(name)
+            | Action::Signal(_, name, _)
+            | Action::Tail(name, _, _, _) => Target::Task(name),
+            Action::Restart(arg) | Action::Start(arg) | Action::Stop(arg) => match arg {
+                Some((name, _)) => Target::Task(name),
+                None => Target::AllTasks,
+            },
+            Action::Status(arg) => match arg {
+                Some(name) => Target::Task(name),
+                None => Target::AllTasks,
+            },
+            Action::HttpLogging(_)
+            | Action::GetHttpLoggingStatus
+            | Action::Maintail(_)
+            | Action::Shutdown
+            | Action::Update(_) => Target::Daemon,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Action {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn reads_indexed_clear_and_tail() {
+        assert!(parse(r#"{"Clear": ["web", 1]}"#) == Action::Clear("web".to_string(), Some(1)));
+        assert!(
+            parse(r#"{"Tail": ["web", null, "Stderr", {"Fixed": 10}]}"#)
+                == Action::Tail(
+                    "web".to_string(),
+                    None,
+                    OutputType::Stderr,
+                    TailType::Fixed(Some(10))
+                )
+        );
+    }
+
+    #[test]
+    fn reads_legacy_clear_and_tail() {
+        assert!(parse(r#"{"Clear": "web"}"#) == Action::Clear("web".to_string(), None));
+        assert!(
+            parse(r#"{"Tail": ["web", "Stdout", {"Stream": null}]}"#)
+                == Action::Tail(
+                    "web".to_string(),
+                    None,
+                    OutputType::Stdout,
+                    TailType::Stream(None)
+                )
+        );
+    }
+
+    #[test]
+    fn rejects_other_shapes() {
+        assert!(serde_json::from_str::<Action>(r#"{"Clear": 1}"#).is_err());
+        assert!(serde_json::from_str::<Action>(r#"{"Tail": ["web", "Stdout"]}"#).is_err());
+    }
+}
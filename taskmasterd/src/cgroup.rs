@@ -0,0 +1,417 @@
+use crate::configuration::Configuration;
+use libc::pid_t;
+use serde::Serialize;
+use std::ffi::CString;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+const CPU_WEIGHT_DEFAULT: u32 = 100;
+const DESTROY_ATTEMPTS: usize = 100;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Usage {
+    pub memory: Option<u64>,
+    pub cpu_usec: Option<u64>,
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1}{}", UNITS[unit])
+}
+
+fn write_file(path: &Path, value: &str) -> Result<(), String> {
+    OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(value.as_bytes()))
+        .map_err(|e| format!("can't write \"{value}\" to {}: {e}", path.display()))
+}
+
+fn is_writable(path: &Path) -> bool {
+    let existing = path
+        .ancestors()
+        .find(|dir| dir.exists())
+        .unwrap_or(Path::new("/"));
+    match CString::new(existing.as_os_str().as_bytes()) {
+        Ok(path) => unsafe {
+            libc::faccessat(libc::AT_FDCWD, path.as_ptr(), libc::W_OK, libc::AT_EACCESS) == 0
+        },
+        Err(_) => false,
+    }
+}
+
+impl Cgroup {
+    fn is_available(root: &Path) -> bool {
+        match root.parent() {
+            Some(parent) => parent.join("cgroup.controllers").exists(),
+            None => false,
+        }
+    }
+
+    pub fn for_task(
+        root: &Path,
+        name: &str,
+        configuration: &Configuration,
+    ) -> Result<Option<Cgroup>, String> {
+        if !configuration.has_cgroup_limits() {
+            return Ok(None);
+        }
+        if !Cgroup::is_available(root) {
+            return Err(format!("cgroup v2 is not mounted above {}", root.display()));
+        }
+        if !is_writable(root) {
+            return Err(format!("{} is not writable", root.display()));
+        }
+        Ok(Some(Cgroup::new(root, name)))
+    }
+
+    pub fn new(root: &Path, name: &str) -> Cgroup {
+        Cgroup {
+            path: root.join(name),
+        }
+    }
+
+    fn enable_controllers(&self, controllers: &[&str]) -> Result<(), String> {
+        let root = self.path.parent().unwrap_or(Path::new("/"));
+        for dir in [root.parent().unwrap_or(Path::new("/")), root] {
+            let subtree_control = dir.join("cgroup.subtree_control");
+            let enabled = fs::read_to_string(&subtree_control).unwrap_or_default();
+            let missing: Vec<String> = controllers
+                .iter()
+                .filter(|controller| !enabled.split_whitespace().any(|c| c == **controller))
+                .map(|controller| format!("+{controller}"))
+                .collect();
+            if !missing.is_empty() {
+                write_file(&subtree_control, &missing.join(" "))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_limit(&self, file: &str, value: Option<String>, default: &str) -> Result<(), String> {
+        let path = self.path.join(file);
+        match value {
+            Some(value) => write_file(&path, &value),
+            None if path.exists() => write_file(&path, default),
+            None => Ok(()),
+        }
+    }
+
+    pub fn setup(&self, configuration: &Configuration) -> Result<(), String> {
+        let limits = [
+            (
+                "memory",
+                "memory.max",
+                configuration.memory_max.map(|value| value.to_string()),
+                "max".to_string(),
+            ),
+            (
+                "cpu",
+                "cpu.weight",
+                configuration.cpu_weight.map(|value| value.to_string()),
+                CPU_WEIGHT_DEFAULT.to_string(),
+            ),
+            (
+                "cpu",
+                "cpu.max",
+                configuration.cpu_max.clone(),
+                "max".to_string(),
+            ),
+            (
+                "pids",
+                "pids.max",
+                configuration.pids_max.map(|value| value.to_string()),
+                "max".to_string(),
+            ),
+        ];
+        let mut controllers: Vec<&str> = Vec::new();
+        for (controller, _, value, _) in &limits {
+            if value.is_some() && !controllers.contains(controller) {
+                controllers.push(controller);
+            }
+        }
+        self.enable_controllers(&controllers)?;
+        fs::create_dir_all(&self.path)
+            .map_err(|e| format!("can't create {}: {e}", self.path.display()))?;
+        for (_, file, value, default) in limits {
+            self.set_limit(file, value, &default)?;
+        }
+        Ok(())
+    }
+
+    pub fn open_procs(&self) -> Result<File, String> {
+        let path = self.path.join("cgroup.procs");
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .map_err(|e| format!("can't open {}: {e}", path.display()))
+    }
+
+    pub fn pids(&self) -> Vec<pid_t> {
+        fs::read_to_string(self.path.join("cgroup.procs"))
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect()
+    }
+
+    pub fn kill(&self) -> Result<usize, String> {
+        let pids = self.pids();
+        if pids.is_empty() {
+            return Ok(0);
+        }
+        let kill_file = self.path.join("cgroup.kill");
+        if kill_file.exists() {
+            write_file(&kill_file, "1")?;
+        } else {
+            for pid in &pids {
+                unsafe {
+                    libc::kill(*pid, libc::SIGKILL);
+                }
+            }
+        }
+        Ok(pids.len())
+    }
+
+    pub fn destroy(&self) -> Result<(), String> {
+        self.kill()?;
+        for _ in 0..DESTROY_ATTEMPTS {
+            if self.pids().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.remove()
+    }
+
+    fn remove(&self) -> Result<(), String> {
+        match fs::remove_dir(&self.path) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("can't remove {}: {e}", self.path.display())),
+        }
+    }
+
+    pub fn usage(&self) -> Option<Usage> {
+        let memory = fs::read_to_string(self.path.join("memory.current"))
+            .ok()
+            .and_then(|value| value.trim().parse::<u64>().ok());
+        let cpu_usec = fs::read_to_string(self.path.join("cpu.stat"))
+            .ok()
+            .and_then(|stat| {
+                stat.lines()
+                    .find_map(|line| line.strip_prefix("usage_usec "))
+                    .and_then(|value| value.trim().parse::<u64>().ok())
+            });
+        match (memory, cpu_usec) {
+            (None, None) => None,
+            (memory, cpu_usec) => Some(Usage { memory, cpu_usec }),
+        }
+    }
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let parts = [
+            self.memory
+                .map(|bytes| format!("memory {}", format_bytes(bytes))),
+            self.cpu_usec
+                .map(|usec| format!("cpu {:.2}s", usec as f64 / 1_000_000.0)),
+        ];
+        write!(
+            f,
+            "{}",
+            parts
+                .into_iter()
+                .flatten()
+                .collect::<Vec<String>>()
+                .join(", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeCgroupFs {
+        base: PathBuf,
+    }
+
+    impl FakeCgroupFs {
+        fn new(name: &str) -> FakeCgroupFs {
+            let base = std::env::temp_dir()
+                .join(format!("taskmaster-cgroup-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&base);
+            fs::create_dir_all(base.join("taskmaster")).unwrap();
+            fs::write(base.join("cgroup.controllers"), "cpu memory pids").unwrap();
+            fs::write(base.join("cgroup.subtree_control"), "").unwrap();
+            fs::write(base.join("taskmaster/cgroup.subtree_control"), "").unwrap();
+            FakeCgroupFs { base }
+        }
+
+        fn root(&self) -> PathBuf {
+            self.base.join("taskmaster")
+        }
+
+        fn add_group(&self, name: &str, files: &[(&str, &str)]) -> PathBuf {
+            let path = self.root().join(name);
+            fs::create_dir_all(&path).unwrap();
+            for (file, content) in files {
+                fs::write(path.join(file), content).unwrap();
+            }
+            path
+        }
+    }
+
+    impl Drop for FakeCgroupFs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.base);
+        }
+    }
+
+    fn limited() -> Configuration {
+        Configuration {
+            memory_max: Some(64 << 20),
+            ..Configuration::default()
+        }
+    }
+
+    #[test]
+    fn no_cgroup_without_limits() {
+        let fs = FakeCgroupFs::new("no-limits");
+        assert_eq!(
+            Cgroup::for_task(&fs.root(), "web", &Configuration::default()),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn cgroup_with_limits() {
+        let fs = FakeCgroupFs::new("limits");
+        assert_eq!(
+            Cgroup::for_task(&fs.root(), "web", &limited()),
+            Ok(Some(Cgroup::new(&fs.root(), "web")))
+        );
+    }
+
+    #[test]
+    fn limits_need_cgroup_v2() {
+        let fs = FakeCgroupFs::new("no-v2");
+        fs::remove_file(fs.base.join("cgroup.controllers")).unwrap();
+        assert!(Cgroup::for_task(&fs.root(), "web", &limited()).is_err());
+    }
+
+    #[test]
+    fn setup_writes_limits_and_enables_controllers() {
+        let fs = FakeCgroupFs::new("setup");
+        let path = fs.add_group(
+            "web",
+            &[
+                ("memory.max", "max"),
+                ("cpu.weight", "100"),
+                ("cpu.max", "max 100000"),
+                ("pids.max", "max"),
+            ],
+        );
+        let configuration = Configuration {
+            cpu_max: Some("50000 100000".to_string()),
+            pids_max: Some(32),
+            ..limited()
+        };
+        Cgroup::new(&fs.root(), "web")
+            .setup(&configuration)
+            .unwrap();
+        let read = |file: &str| fs::read_to_string(path.join(file)).unwrap();
+        assert_eq!(read("memory.max"), (64 << 20).to_string());
+        assert_eq!(read("cpu.weight"), "100");
+        assert_eq!(read("cpu.max"), "50000 100000");
+        assert_eq!(read("pids.max"), "32");
+        for dir in [fs.base.clone(), fs.root()] {
+            assert_eq!(
+                fs::read_to_string(dir.join("cgroup.subtree_control")).unwrap(),
+                "+memory +cpu +pids"
+            );
+        }
+    }
+
+    #[test]
+    fn setup_skips_enabled_controllers() {
+        let fs = FakeCgroupFs::new("enabled");
+        fs::write(fs.base.join("cgroup.subtree_control"), "cpu memory").unwrap();
+        fs.add_group("web", &[("memory.max", "max"), ("pids.max", "max")]);
+        Cgroup::new(&fs.root(), "web")
+            .setup(&Configuration {
+                pids_max: Some(8),
+                ..limited()
+            })
+            .unwrap();
+        let read = |dir: PathBuf| fs::read_to_string(dir.join("cgroup.subtree_control")).unwrap();
+        assert_eq!(read(fs.base.clone()), "+pids");
+        assert_eq!(read(fs.root()), "+memory +pids");
+    }
+
+    #[test]
+    fn setup_resets_removed_limits() {
+        let fs = FakeCgroupFs::new("reset");
+        let path = fs.add_group("web", &[("memory.max", "1024"), ("pids.max", "8")]);
+        Cgroup::new(&fs.root(), "web")
+            .setup(&Configuration {
+                memory_max: None,
+                pids_max: Some(16),
+                ..Configuration::default()
+            })
+            .unwrap();
+        assert_eq!(fs::read_to_string(path.join("memory.max")).unwrap(), "max");
+        assert_eq!(fs::read_to_string(path.join("pids.max")).unwrap(), "16");
+        assert_eq!(
+            fs::read_to_string(fs.root().join("cgroup.subtree_control")).unwrap(),
+            "+pids"
+        );
+    }
+
+    #[test]
+    fn reads_pids_and_usage() {
+        let fs = FakeCgroupFs::new("usage");
+        fs.add_group(
+            "web",
+            &[
+                ("cgroup.procs", ""),
+                ("memory.current", "1048576\n"),
+                ("cpu.stat", "usage_usec 2500000\nuser_usec 2000000\n"),
+            ],
+        );
+        let cgroup = Cgroup::new(&fs.root(), "web");
+        assert!(cgroup.pids().is_empty());
+        assert_eq!(cgroup.kill(), Ok(0));
+        let usage = cgroup.usage().unwrap();
+        assert_eq!(usage.memory, Some(1 << 20));
+        assert_eq!(usage.cpu_usec, Some(2_500_000));
+        assert_eq!(usage.to_string(), "memory 1.0MiB, cpu 2.50s");
+    }
+
+    #[test]
+    fn destroy_removes_the_group() {
+        let fs = FakeCgroupFs::new("destroy");
+        let path = fs.add_group("web", &[]);
+        Cgroup::new(&fs.root(), "web").destroy().unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+
+pub const DEFAULT_SHELL: &str = "/bin/sh";
+
+const SHELL_OPERATORS: &[char] = &['|', '&', ';', '<', '>', '(', ')'];
+
+struct Tokenizer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    env: &'a BTreeMap<String, String>,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn lookup(&self, name: &str) -> Option<String> {
+        match self.env.get(name) {
+            Some(value) => Some(value.clone()),
+            None => std::env::var(name).ok(),
+        }
+    }
+
+    fn default_value(&mut self, name: &str) -> Result<String, String> {
+        if self.chars.next() != Some('-') {
+            return Err(format!("expected ':-' after ${{{name}"));
+        }
+        let mut default = String::new();
+        loop {
+            match self.chars.next() {
+                Some('}') => return Ok(default),
+                Some(c) => default.push(c),
+                None => return Err(format!("unterminated ${{{name}:-{default}")),
+            }
+        }
+    }
+
+    fn expand_variable(&mut self, word: &mut String) -> Result<(), String> {
+        match self.chars.peek() {
+            Some('{') => {
+                self.chars.next();
+                let mut name = String::new();
+                let mut default = None;
+                loop {
+                    match self.chars.next() {
+                        Some('}') => break,
+                        Some(':') if !name.is_empty() => {
+                            default = Some(self.default_value(&name)?);
+                            break;
+                        }
+                        Some(c) if c == '_' || c.is_ascii_alphanumeric() => name.push(c),
+                        Some(c) => return Err(format!("invalid character '{c}' in ${{{name}")),
+                        None => return Err(format!("unterminated ${{{name}")),
+                    }
+                }
+                if name.is_empty() {
+                    return Err("empty variable name in ${}".to_string());
+                }
+                let value = self.lookup(&name);
+                match default {
+                    Some(default) if value.as_ref().is_none_or(|value| value.is_empty()) => {
+                        word.push_str(&default)
+                    }
+                    _ => word.push_str(&value.unwrap_or_default()),
+                }
+            }
+            Some('(') => {
+                return Err("command substitution $(...) requires the shell option".to_string())
+            }
+            Some(&c) if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c != '_' && !c.is_ascii_alphanumeric() {
+                        break;
+                    }
+                    name.push(c);
+                    self.chars.next();
+                }
+                word.push_str(&self.lookup(&name).unwrap_or_default());
+            }
+            _ => word.push('$'),
+        }
+        Ok(())
+    }
+
+    fn single_quoted(&mut self, word: &mut String) -> Result<(), String> {
+        loop {
+            match self.chars.next() {
+                Some('\'') => return Ok(()),
+                Some(c) => word.push(c),
+                None => return Err("unterminated single quote".to_string()),
+            }
+        }
+    }
+
+    fn double_quoted(&mut self, word: &mut String) -> Result<(), String> {
+        loop {
+            match self.chars.next() {
+                Some('"') => return Ok(()),
+                Some('\\') => match self.chars.next() {
+                    Some(c @ ('$' | '`' | '"' | '\\')) => word.push(c),
+                    Some('\n') => {}
+                    Some(c) => {
+                        word.push('\\');
+                        word.push(c);
+                    }
+                    None => return Err("unterminated double quote".to_string()),
+                },
+                Some('$') => self.expand_variable(word)?,
+                Some('`') => {
+                    return Err("command substitution `...` requires the shell option".to_string())
+                }
+                Some(c) => word.push(c),
+                None => return Err("unterminated double quote".to_string()),
+            }
+        }
+    }
+
+    fn split(mut self) -> Result<Vec<String>, String> {
+        let mut words = Vec::new();
+        let mut word = String::new();
+        let mut in_word = false;
+        while let Some(c) = self.chars.next() {
+            match c {
+                c if c.is_whitespace() => {
+                    if in_word {
+                        words.push(std::mem::take(&mut word));
+                        in_word = false;
+                    }
+                    continue;
+                }
+                '\'' => self.single_quoted(&mut word)?,
+                '"' => self.double_quoted(&mut word)?,
+                '\\' => match self.chars.next() {
+                    Some('\n') => {}
+                    Some(c) => word.push(c),
+                    None => return Err("trailing backslash".to_string()),
+                },
+                '$' => self.expand_variable(&mut word)?,
+                '`' => {
+                    return Err("command substitution `...` requires the shell option".to_string())
+                }
+                c if SHELL_OPERATORS.contains(&c) => {
+                    return Err(format!(
+                        "'{c}' is a shell operator, it requires the shell option"
+                    ))
+                }
+                c => word.push(c),
+            }
+            in_word = true;
+        }
+        if in_word {
+            words.push(word);
+        }
+        if words.is_empty() {
+            return Err("empty command".to_string());
+        }
+        Ok(words)
+    }
+}
+
+pub fn split(cmd: &str, env: &BTreeMap<String, String>) -> Result<Vec<String>, String> {
+    Tokenizer {
+        chars: cmd.chars().peekable(),
+        env,
+    }
+    .split()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Configuration;
+
+    fn words(cmd: &str) -> Result<Vec<String>, String> {
+        let env = BTreeMap::from([
+            ("NAME".to_string(), "task master".to_string()),
+            ("EMPTY".to_string(), String::new()),
+        ]);
+        split(cmd, &env)
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(words("  ls   -l\t/tmp  ").unwrap(), ["ls", "-l", "/tmp"]);
+    }
+
+    #[test]
+    fn keeps_quoted_words_together() {
+        assert_eq!(
+            words(r#"echo 'a  b' "c  d" e'f'"g""#).unwrap(),
+            ["echo", "a  b", "c  d", "efg"]
+        );
+        assert_eq!(words("echo ''").unwrap(), ["echo", ""]);
+    }
+
+    #[test]
+    fn handles_escapes() {
+        assert_eq!(words(r"echo a\ b \'c\'").unwrap(), ["echo", "a b", "'c'"]);
+        assert_eq!(
+            words(r#"echo "\$NAME \"q\" \\ \n""#).unwrap(),
+            ["echo", r#"$NAME "q" \ \n"#]
+        );
+        assert_eq!(words("echo a\\\nb").unwrap(), ["echo", "ab"]);
+    }
+
+    #[test]
+    fn expands_variables() {
+        assert_eq!(
+            words(r#"echo $NAME "$NAME" '$NAME'"#).unwrap(),
+            ["echo", "task master", "task master", "$NAME"]
+        );
+        assert_eq!(
+            words("echo ${EMPTY:-default} ${NAME:-default} ${UNSET_TASKMASTER_VARIABLE}").unwrap(),
+            ["echo", "default", "task master", ""]
+        );
+        assert_eq!(words("echo $ 5$").unwrap(), ["echo", "$", "5$"]);
+    }
+
+    #[test]
+    fn rejects_shell_syntax() {
+        for cmd in [
+            "echo a | wc",
+            "echo a; echo b",
+            "echo a && echo b",
+            "echo a > file",
+            "(echo a)",
+            "echo $(date)",
+            "echo `date`",
+            "echo \"`date`\"",
+        ] {
+            assert!(words(cmd).unwrap_err().contains("shell option"), "{cmd}");
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        assert_eq!(words("echo 'a").unwrap_err(), "unterminated single quote");
+        assert_eq!(words("echo \"a").unwrap_err(), "unterminated double quote");
+        assert_eq!(words("echo a\\").unwrap_err(), "trailing backslash");
+        assert_eq!(words("echo ${NAME").unwrap_err(), "unterminated ${NAME");
+        assert_eq!(words("echo ${}").unwrap_err(), "empty variable name in ${}");
+        assert_eq!(words("   ").unwrap_err(), "empty command");
+    }
+
+    #[test]
+    fn shell_option_bypasses_the_tokenizer() {
+        let configuration = Configuration {
+            cmd: "echo $(date) | wc -c".to_string(),
+            shell: Some(DEFAULT_SHELL.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            configuration.command_line().unwrap(),
+            [DEFAULT_SHELL, "-c", "echo $(date) | wc -c"]
+        );
+    }
+}
@@ -1,9 +1,24 @@
+use crate::access::{Access, AccessRule};
+use crate::command_line::{self, DEFAULT_SHELL};
+use crate::diagnostic::{from_yaml_value, key_name, unknown_key_message, Diagnostics};
+use crate::exit::ExitSignal;
+use crate::health::HealthCheck;
+use crate::identity::Identity;
+use crate::interpolation::{self, Context, Mode};
 use crate::logger::Logger;
+use crate::rlimit::{Resource, Rlimit};
+use crate::settings::{
+    deserialize_octal, parse_umask, settings, LogLevel, Settings, DAEMON_SECTION,
+};
+use crate::utils::glob;
 use serde::{Deserialize, Deserializer, Serialize};
-use std::collections::BTreeMap;
+use serde_yaml::{Mapping, Value};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Display, Formatter};
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use validator::{Validate, ValidationError};
@@ -29,12 +44,39 @@ pub enum StopSignal {
     USR2 = libc::SIGUSR2 as isize,
 }
 
+pub const ACCESS_SECTION: &str = "access";
+pub const DEFAULTS_SECTION: &str = "defaults";
+pub const PROGRAMS_SECTION: &str = "programs";
+pub const INCLUDE_SECTION: &str = "include";
+const TOP_LEVEL_SECTIONS: [&str; 5] = [
+    DAEMON_SECTION,
+    DEFAULTS_SECTION,
+    PROGRAMS_SECTION,
+    ACCESS_SECTION,
+    INCLUDE_SECTION,
+];
+const VALIDATION_PROGRAM_NAME: &str = "program";
+const AUTO_LOG: &str = "AUTO";
+const NONE_LOG: &str = "NONE";
+const INCLUDED_FILE_SECTIONS: [&str; 2] = [DEFAULTS_SECTION, PROGRAMS_SECTION];
+const MAIN_FILE_SECTIONS: [&str; 3] = [DAEMON_SECTION, ACCESS_SECTION, INCLUDE_SECTION];
+const DEFAULT_PRIORITY: u32 = 999;
+const DEFAULT_EXIT_HISTORY: u32 = 10;
+
+pub struct ConfigFile {
+    pub tasks: BTreeMap<String, Configuration>,
+    pub sources: BTreeMap<String, String>,
+    pub access: Access,
+    pub daemon: Option<Settings>,
+}
+
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum State {
     STOPPED(Option<SystemTime>),
     STARTING(SystemTime),
     RUNNING(SystemTime),
-    BACKOFF,
+    UNHEALTHY(SystemTime),
+    BACKOFF(SystemTime),
     STOPPING(SystemTime),
     EXITED(SystemTime),
     FATAL(String),
@@ -69,7 +111,7 @@ impl Into<libc::c_int> for StopSignal {
 }
 
 impl State {
-    fn at(time_stamp: &SystemTime) -> String {
+    pub fn at(time_stamp: &SystemTime) -> String {
         let since_the_epoch = time_stamp
             .duration_since(UNIX_EPOCH)
             .unwrap_or(Duration::from_secs(0));
@@ -79,6 +121,30 @@ impl State {
         let seconds = now_in_sec % 60;
         format!("at {:02}:{:02}:{:02}", hours, minutes, seconds)
     }
+
+    fn uptime(start_time: &SystemTime) -> String {
+        let elapsed_time = SystemTime::now()
+            .duration_since(*start_time)
+            .unwrap_or(Duration::from_secs(0));
+        let elapsed_time_seconds = elapsed_time.as_secs();
+        let hours = elapsed_time_seconds / 3600;
+        let minutes = (elapsed_time_seconds % 3600) / 60;
+        let seconds = elapsed_time_seconds % 60;
+        format!("uptime {:02}:{:02}:{:02}", hours, minutes, seconds)
+    }
+
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            State::STOPPED(_) => "stopped",
+            State::STARTING(_) => "starting",
+            State::RUNNING(_) => "running",
+            State::UNHEALTHY(_) => "unhealthy",
+            State::BACKOFF(_) => "backoff",
+            State::STOPPING(_) => "stopping",
+            State::EXITED(_) => "exited",
+            State::FATAL(_) => "fatal",
+        }
+    }
 }
 
 impl Display for State {
@@ -92,20 +158,12 @@ impl Display for State {
             },
             State::STARTING(_) => "starting".to_string(),
             State::RUNNING(start_time) => {
-                let current_time = SystemTime::now();
-                let elapsed_time = current_time
-                    .duration_since(start_time.clone())
-                    .unwrap_or(Duration::from_secs(0));
-                let elapsed_time_seconds = elapsed_time.as_secs();
-                let hours = elapsed_time_seconds / 3600;
-                let minutes = (elapsed_time_seconds % 3600) / 60;
-                let seconds = elapsed_time_seconds % 60;
-                format!(
-                    "running (uptime {:02}:{:02}:{:02})",
-                    hours, minutes, seconds
-                )
+                format!("running ({})", State::uptime(start_time))
+            }
+            State::UNHEALTHY(start_time) => {
+                format!("unhealthy ({})", State::uptime(start_time))
             }
-            State::BACKOFF => "backoff".to_string(),
+            State::BACKOFF(until) => format!("backoff (retry {})", State::at(until)),
             State::EXITED(exited_at) => {
                 format!("exited {}", State::at(exited_at))
             }
@@ -119,7 +177,8 @@ impl Display for State {
 }
 
 #[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Validate)]
-#[serde(default)]
+#[serde(default, deny_unknown_fields)]
+#[validate(schema(function = "validate_configuration", skip_on_field_errors = false))]
 pub struct Configuration {
     #[serde(deserialize_with = "deserialize_string_and_trim")]
     #[validate(length(
@@ -128,6 +187,8 @@ pub struct Configuration {
         message = "cmd: can't be empty or bigger than 1024"
     ))]
     pub cmd: String,
+    #[serde(deserialize_with = "deserialize_shell")]
+    pub shell: Option<String>,
     #[validate(range(
         min = 1,
         max = 1024,
@@ -139,9 +200,37 @@ pub struct Configuration {
     pub umask: u32,
     #[serde(deserialize_with = "deserialize_option_string_and_trim")]
     pub working_dir: Option<String>,
+    #[serde(deserialize_with = "deserialize_option_string_and_trim")]
+    pub user: Option<String>,
+    #[serde(deserialize_with = "deserialize_option_string_and_trim")]
+    pub group: Option<String>,
+    pub supplementary_groups: Vec<String>,
+    pub rlimits: BTreeMap<Resource, Rlimit>,
+    #[serde(deserialize_with = "deserialize_option_bytes")]
+    pub memory_max: Option<u64>,
+    #[validate(range(
+        min = 1,
+        max = 10000,
+        message = "cpu_weight value should be between 1 and 10000"
+    ))]
+    pub cpu_weight: Option<u32>,
+    #[serde(deserialize_with = "deserialize_option_string_and_trim")]
+    #[validate(custom = "validate_cpu_max")]
+    pub cpu_max: Option<String>,
+    #[validate(range(min = 1, message = "pids_max value should be at least 1"))]
+    pub pids_max: Option<u64>,
     pub auto_start: bool,
     pub auto_restart: AutoRestart,
+    pub depends_on: Vec<String>,
+    pub priority: u32,
     pub exit_codes: Vec<i32>,
+    pub exit_signals: Vec<ExitSignal>,
+    #[validate(range(
+        min = 1,
+        max = 1024,
+        message = "exit_history value should be between 1 and 1024"
+    ))]
+    pub exit_history: u32,
     #[validate(range(
         min = 0,
         max = 1024,
@@ -149,13 +238,35 @@ pub struct Configuration {
     ))]
     pub start_retries: u32,
     pub start_time: u64,
+    pub backoff_initial: u64,
+    pub backoff_max: u64,
+    #[validate(range(
+        min = 1,
+        max = 10,
+        message = "backoff_multiplier value should be between 1 and 10"
+    ))]
+    pub backoff_multiplier: u32,
+    #[validate(range(max = 100, message = "backoff_jitter: max=100"))]
+    pub backoff_jitter: u32,
+    pub backoff_reset: u64,
+    #[validate(range(
+        min = 1,
+        message = "max_restarts_per_window value should be at least 1"
+    ))]
+    #[serde(deserialize_with = "deserialize_restart_limit")]
+    pub max_restarts_per_window: Option<u32>,
+    #[validate(range(min = 1, message = "restart_window value should be at least 1"))]
+    pub restart_window: u64,
     pub stop_signal: StopSignal,
     #[validate(range(min = 1, message = "invalid stop_time"))]
     pub stop_time: u64,
+    pub stopasgroup: bool,
+    pub killasgroup: bool,
     #[serde(deserialize_with = "deserialize_option_string_and_trim")]
     pub stdout: Option<String>,
     #[serde(deserialize_with = "deserialize_option_string_and_trim")]
     pub stderr: Option<String>,
+    pub redirect_stderr: bool,
     pub env: BTreeMap<String, String>,
     #[validate(range(
         min = 1024,
@@ -163,71 +274,621 @@ pub struct Configuration {
         message = "logfile_maxbytes: min=1kB(1024), max=100MB(104857600)"
     ))]
     pub logfile_maxbytes: u64,
+    #[validate(range(max = 100, message = "logfile_backups: max=100"))]
+    pub logfile_backups: u32,
+    pub logfile_compress: bool,
+    #[validate(range(max = 100000, message = "log_buffer_lines: max=100000"))]
+    pub log_buffer_lines: usize,
+    pub log_timestamps: bool,
+    pub log_prefix: bool,
+    pub log_syslog: bool,
+    #[serde(deserialize_with = "deserialize_option_string_and_trim")]
+    pub log_http: Option<String>,
+    pub health_check: Option<HealthCheck>,
 }
 
 impl Default for Configuration {
     fn default() -> Self {
         Self {
             cmd: String::new(),
+            shell: None,
             num_procs: 1,
             umask: 0o022,
             working_dir: None,
+            user: None,
+            group: None,
+            supplementary_groups: Vec::new(),
+            rlimits: BTreeMap::new(),
+            memory_max: None,
+            cpu_weight: None,
+            cpu_max: None,
+            pids_max: None,
             auto_start: true,
             auto_restart: AutoRestart::Unexpected,
+            depends_on: Vec::new(),
+            priority: DEFAULT_PRIORITY,
             exit_codes: vec![0],
+            exit_signals: Vec::new(),
+            exit_history: DEFAULT_EXIT_HISTORY,
             start_retries: 3,
             start_time: 1,
+            backoff_initial: 1,
+            backoff_max: 60,
+            backoff_multiplier: 2,
+            backoff_jitter: 20,
+            backoff_reset: 60,
+            max_restarts_per_window: None,
+            restart_window: 60,
             stop_signal: StopSignal::TERM,
             stop_time: 10,
+            stopasgroup: false,
+            killasgroup: false,
             stdout: None,
             stderr: None,
+            redirect_stderr: false,
             env: Default::default(),
             logfile_maxbytes: 10 << 20,
+            logfile_backups: 10,
+            logfile_compress: false,
+            log_buffer_lines: 1000,
+            log_timestamps: false,
+            log_prefix: false,
+            log_syslog: false,
+            log_http: None,
+            health_check: None,
         }
     }
 }
 
 impl Configuration {
-    pub fn from_yml(
-        path: String,
-        logger: Arc<Mutex<Logger>>,
-    ) -> Result<BTreeMap<String, Configuration>, String> {
-        let mut logger = logger.lock().unwrap();
-        logger.log(format!("Reading {path}"));
-        let mut file = File::open(&path).map_err(|err| format!("{}: {}", path, err))?;
+    pub fn command_line(&self) -> Result<Vec<String>, String> {
+        match &self.shell {
+            Some(shell) => Ok(vec![shell.clone(), "-c".to_string(), self.cmd.clone()]),
+            None => command_line::split(&self.cmd, &self.env),
+        }
+    }
+
+    pub fn captures_output(&self, log_file: &Option<String>) -> bool {
+        log_file.is_some() || self.log_syslog || self.log_http.is_some()
+    }
+
+    pub fn expand(&self, program_name: &str, process_num: usize) -> Result<Configuration, String> {
+        let context = Context {
+            program_name,
+            process_num,
+        };
+        let expand = |field: &str, value: &str, mode: Mode| {
+            interpolation::expand(value, &context, mode).map_err(|err| format!("{field}: {err}"))
+        };
+        let expand_path = |field: &str, value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| expand(field, value, Mode::Value))
+                .transpose()
+        };
+        let expand_log_path = |field: &str, value: &Option<String>| -> Result<_, String> {
+            match value.as_deref() {
+                _ if is_auto_log(value) => Ok(Some(format!(
+                    "{}/{program_name}-{process_num}-{field}.log",
+                    settings().child_log_dir.trim_end_matches('/')
+                ))),
+                Some(value) if value.eq_ignore_ascii_case(NONE_LOG) => Ok(None),
+                _ => expand_path(field, value),
+            }
+        };
+        Ok(Configuration {
+            cmd: expand("cmd", &self.cmd, Mode::Command)?,
+            working_dir: expand_path("working_dir", &self.working_dir)?,
+            stdout: expand_log_path("stdout", &self.stdout)?,
+            stderr: match self.redirect_stderr {
+                true => None,
+                false => expand_log_path("stderr", &self.stderr)?,
+            },
+            log_http: expand_path("log_http", &self.log_http)?,
+            health_check: self
+                .health_check
+                .as_ref()
+                .map(|health_check| {
+                    health_check.expand(|field, value, mode| {
+                        value
+                            .as_deref()
+                            .map(|value| expand(field, value, mode))
+                            .transpose()
+                    })
+                })
+                .transpose()?,
+            env: self
+                .env
+                .iter()
+                .map(|(key, value)| {
+                    Ok((
+                        key.clone(),
+                        expand(&format!("env.{key}"), value, Mode::Value)?,
+                    ))
+                })
+                .collect::<Result<_, String>>()?,
+            ..self.clone()
+        })
+    }
+
+    pub fn has_cgroup_limits(&self) -> bool {
+        self.memory_max.is_some()
+            || self.cpu_weight.is_some()
+            || self.cpu_max.is_some()
+            || self.pids_max.is_some()
+    }
+
+    fn program_path(sectioned: bool, name: &str) -> Vec<&str> {
+        match sectioned {
+            true => vec![PROGRAMS_SECTION, name],
+            false => vec![name],
+        }
+    }
+
+    fn programs_from_document(
+        diagnostics: &mut Diagnostics,
+        mut document: Mapping,
+        inherited_defaults: &Mapping,
+        sections: &[&str],
+    ) -> (BTreeMap<String, Configuration>, Mapping) {
+        let sectioned =
+            document.contains_key(PROGRAMS_SECTION) || document.contains_key(DEFAULTS_SECTION);
+        let (defaults, programs) = if sectioned {
+            let file_defaults = document
+                .remove(DEFAULTS_SECTION)
+                .and_then(|value| diagnostics.mapping(DEFAULTS_SECTION, &[DEFAULTS_SECTION], value))
+                .map(|mapping| {
+                    let mapping = diagnostics.remove_unknown_keys::<Configuration>(
+                        DEFAULTS_SECTION,
+                        &[DEFAULTS_SECTION],
+                        mapping,
+                    );
+                    diagnostics
+                        .deserialize::<Configuration>(
+                            DEFAULTS_SECTION,
+                            &[&[DEFAULTS_SECTION]],
+                            mapping,
+                        )
+                        .1
+                })
+                .unwrap_or_default();
+            let mut defaults = inherited_defaults.clone();
+            defaults.extend(file_defaults);
+            let programs = document
+                .remove(PROGRAMS_SECTION)
+                .and_then(|value| diagnostics.mapping(PROGRAMS_SECTION, &[PROGRAMS_SECTION], value))
+                .unwrap_or_default();
+            for key in document.keys().map(key_name) {
+                let position = diagnostics.locate(&[key.as_str()]);
+                diagnostics.error(&key, position, unknown_key_message(&key, sections));
+            }
+            (defaults, programs)
+        } else {
+            (inherited_defaults.clone(), document)
+        };
+        let mut tasks = BTreeMap::new();
+        for (name, program) in programs {
+            let name = key_name(&name);
+            let path = Self::program_path(sectioned, &name);
+            let Some(program) = diagnostics.mapping(&name, &path, program) else {
+                continue;
+            };
+            let mut merged = defaults.clone();
+            merged.extend(diagnostics.remove_unknown_keys::<Configuration>(&name, &path, program));
+            let paths: [&[&str]; 2] = [&path, &[DEFAULTS_SECTION]];
+            let Some(configuration) = diagnostics
+                .deserialize::<Configuration>(&name, &paths, merged)
+                .0
+            else {
+                continue;
+            };
+            if let Err(e) = configuration.validate() {
+                for (field, errors) in e.field_errors() {
+                    for validation_error in errors {
+                        let message = match &validation_error.message {
+                            Some(message) => message.to_string(),
+                            None => format!("{field}: {}", validation_error.code),
+                        };
+                        let position = match field {
+                            "__all__" => diagnostics.locate_message(&paths, &message),
+                            field => diagnostics.locate_key(&paths, Some(field)),
+                        };
+                        diagnostics.error(&name, position, message);
+                    }
+                }
+            }
+            tasks.insert(name, configuration);
+        }
+        (tasks, defaults)
+    }
+
+    fn include_patterns(diagnostics: &mut Diagnostics, value: Value) -> Vec<String> {
+        let patterns = match value {
+            Value::String(pattern) => return vec![pattern],
+            Value::Sequence(patterns) => patterns,
+            _ => Vec::new(),
+        };
+        match patterns
+            .into_iter()
+            .map(|pattern| match pattern {
+                Value::String(pattern) => Some(pattern),
+                _ => None,
+            })
+            .collect::<Option<Vec<String>>>()
+        {
+            Some(patterns) if !patterns.is_empty() => patterns,
+            _ => {
+                let position = diagnostics.locate(&[INCLUDE_SECTION]);
+                diagnostics.error(
+                    INCLUDE_SECTION,
+                    position,
+                    "expected a file pattern or a list of file patterns",
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    fn included_files(
+        diagnostics: &mut Diagnostics,
+        path: &str,
+        patterns: &[String],
+    ) -> Vec<PathBuf> {
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let mut files = Vec::new();
+        for pattern in patterns {
+            match glob(&directory.join(pattern)) {
+                Ok(matches) => files.extend(matches),
+                Err(err) => {
+                    let position = diagnostics.locate(&[INCLUDE_SECTION]);
+                    diagnostics.error(INCLUDE_SECTION, position, err);
+                }
+            }
+        }
+        let main_file = fs::canonicalize(path).ok();
+        let mut seen = Vec::new();
+        files.retain(|file| {
+            let canonical = fs::canonicalize(file).ok();
+            if canonical == main_file || seen.contains(&canonical) {
+                return false;
+            }
+            seen.push(canonical);
+            true
+        });
+        files
+    }
+
+    fn load_included_file(
+        diagnostics: &mut Diagnostics,
+        file: &Path,
+        defaults: &Mapping,
+    ) -> BTreeMap<String, Configuration> {
+        let name = file.display().to_string();
+        let content = match fs::read_to_string(file) {
+            Ok(content) => content,
+            Err(err) => {
+                diagnostics.error(INCLUDE_SECTION, None, format!("{name}: {err}"));
+                return BTreeMap::new();
+            }
+        };
+        let mut document = match serde_yaml::from_str::<Option<Mapping>>(&content) {
+            Ok(document) => document.unwrap_or_default(),
+            Err(err) => {
+                diagnostics.error(INCLUDE_SECTION, None, format!("{name}: {err}"));
+                return BTreeMap::new();
+            }
+        };
+        let mut included = Diagnostics::with_file(&content, name);
+        for section in MAIN_FILE_SECTIONS {
+            if document.remove(section).is_some() {
+                let position = included.locate(&[section]);
+                included.error(
+                    section,
+                    position,
+                    "only allowed in the main configuration file",
+                );
+            }
+        }
+        let (programs, _) = Configuration::programs_from_document(
+            &mut included,
+            document,
+            defaults,
+            &INCLUDED_FILE_SECTIONS,
+        );
+        diagnostics.absorb(included);
+        programs
+    }
+
+    fn check_dependencies(
+        diagnostics: &mut Diagnostics,
+        path: &str,
+        tasks: &BTreeMap<String, Configuration>,
+        sources: &BTreeMap<String, String>,
+    ) {
+        let locate = |diagnostics: &Diagnostics, name: &str| match sources.get(name) {
+            Some(source) if source == path => {
+                diagnostics.locate(&[PROGRAMS_SECTION, name, "depends_on"])
+            }
+            _ => None,
+        };
+        for (name, configuration) in tasks {
+            for dependency in &configuration.depends_on {
+                if !tasks.contains_key(dependency) {
+                    let position = locate(diagnostics, name);
+                    let message = format!("depends_on: unknown program \"{dependency}\"");
+                    diagnostics.error(name, position, message);
+                }
+            }
+        }
+        if let Err(cycle) = start_order(tasks) {
+            let position = locate(diagnostics, &cycle[0]);
+            let message = format!("depends_on: dependency cycle {}", cycle.join(" -> "));
+            diagnostics.error(&cycle[0], position, message);
+        }
+    }
+
+    pub fn check_yml(path: &str) -> Result<ConfigFile, String> {
+        let mut file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
         let mut content = String::new();
         file.read_to_string(&mut content)
             .map_err(|err| format!("Can't read the file: {err}"))?;
-        let tasks: BTreeMap<String, Configuration> =
+        let mut document: Mapping =
             serde_yaml::from_str(&content).map_err(|err| err.to_string())?;
-        let mut errors = Vec::new();
-        for (key, task) in &tasks {
-            match task.validate() {
-                Ok(_) => {
-                    logger.log(format!("{key}: validated"));
-                }
-                Err(e) => {
-                    for (_k, value) in e.field_errors() {
-                        for validation_error in value {
-                            errors.push(format!(
-                                "Configuration error: {key}: {:?}",
-                                if let Some(message) = validation_error.message.as_ref() {
-                                    message.to_string()
-                                } else {
-                                    validation_error.code.to_string()
-                                }
-                            ));
-                        }
+        let mut diagnostics = Diagnostics::new(&content);
+        let access_rules: Option<Vec<AccessRule>> =
+            document
+                .remove(ACCESS_SECTION)
+                .and_then(|section| match from_yaml_value(section) {
+                    Ok(rules) => Some(rules),
+                    Err(err) => {
+                        let position = diagnostics.locate(&[ACCESS_SECTION]);
+                        diagnostics.error(ACCESS_SECTION, position, err);
+                        None
+                    }
+                });
+        let access = match access_rules {
+            None => Access::default(),
+            Some(rules) => Access::new(&rules).unwrap_or_else(|err| {
+                let position = diagnostics.locate(&[ACCESS_SECTION]);
+                diagnostics.error(ACCESS_SECTION, position, err);
+                Access::default()
+            }),
+        };
+        let daemon = document.remove(DAEMON_SECTION).and_then(|section| {
+            diagnostics.parse_section::<Settings>(DAEMON_SECTION, &[DAEMON_SECTION], section)
+        });
+        let patterns = document
+            .remove(INCLUDE_SECTION)
+            .map(|value| Configuration::include_patterns(&mut diagnostics, value))
+            .unwrap_or_default();
+        let mut defaults = Mapping::new();
+        if let Some(umask) = settings().umask {
+            defaults.insert("umask".into(), format!("{umask:03o}").into());
+        }
+        let (mut tasks, defaults) = Configuration::programs_from_document(
+            &mut diagnostics,
+            document,
+            &defaults,
+            &TOP_LEVEL_SECTIONS,
+        );
+        let mut sources: BTreeMap<String, String> = tasks
+            .keys()
+            .map(|name| (name.clone(), path.to_string()))
+            .collect();
+        for file in Configuration::included_files(&mut diagnostics, path, &patterns) {
+            let file_name = file.display().to_string();
+            let programs = Configuration::load_included_file(&mut diagnostics, &file, &defaults);
+            for (name, configuration) in programs {
+                match sources.get(&name) {
+                    Some(first) => {
+                        let message = format!("defined in both {first} and {file_name}");
+                        diagnostics.error(&name, None, message);
+                    }
+                    None => {
+                        sources.insert(name.clone(), file_name.clone());
+                        tasks.insert(name, configuration);
                     }
                 }
             }
         }
-        if errors.is_empty() {
-            Ok(tasks)
-        } else {
-            Err(errors.join("\n"))
+        Configuration::check_dependencies(&mut diagnostics, path, &tasks, &sources);
+        diagnostics.into_result(ConfigFile {
+            tasks,
+            sources,
+            access,
+            daemon,
+        })
+    }
+
+    pub fn from_yml(path: String, logger: Arc<Mutex<Logger>>) -> Result<ConfigFile, String> {
+        let mut logger = logger.lock().unwrap();
+        logger.log(LogLevel::Info, format!("Reading {path}"));
+        let config_file = Configuration::check_yml(&path)?;
+        if config_file
+            .daemon
+            .as_ref()
+            .is_some_and(|daemon| daemon != settings())
+        {
+            logger.log(
+                LogLevel::Info,
+                format!("{DAEMON_SECTION}: changes will be applied on the next restart"),
+            );
+        }
+        for name in config_file.tasks.keys() {
+            logger.log(LogLevel::Info, format!("{name}: validated"));
+        }
+        Ok(config_file)
+    }
+}
+
+pub fn start_order<'a>(
+    programs: impl IntoIterator<Item = (&'a String, &'a Configuration)>,
+) -> Result<Vec<String>, Vec<String>> {
+    let programs: BTreeMap<&String, &Configuration> = programs.into_iter().collect();
+    let dependencies = |configuration: &'a Configuration| {
+        configuration
+            .depends_on
+            .iter()
+            .filter(|dependency| programs.contains_key(dependency))
+    };
+    let mut pending: BTreeMap<&String, usize> = programs
+        .iter()
+        .map(|(name, configuration)| {
+            let count = dependencies(configuration).collect::<BTreeSet<_>>().len();
+            (*name, count)
+        })
+        .collect();
+    let mut ready: BTreeSet<(u32, &String)> = pending
+        .iter()
+        .filter(|(_, count)| **count == 0)
+        .map(|(name, _)| (programs[name].priority, *name))
+        .collect();
+    let mut order = Vec::with_capacity(programs.len());
+    while let Some((_, name)) = ready.pop_first() {
+        pending.remove(name);
+        order.push(name.clone());
+        for (dependent, configuration) in &programs {
+            if configuration.depends_on.contains(name) {
+                if let Some(count) = pending.get_mut(dependent) {
+                    *count -= 1;
+                    if *count == 0 {
+                        ready.insert((configuration.priority, *dependent));
+                    }
+                }
+            }
         }
     }
+    let Some(mut name) = pending.keys().next().copied() else {
+        return Ok(order);
+    };
+    let mut cycle: Vec<&String> = Vec::new();
+    while !cycle.contains(&name) {
+        cycle.push(name);
+        name = dependencies(programs[name])
+            .find(|dependency| pending.contains_key(dependency))
+            .expect("a program left in a cycle has a pending dependency");
+    }
+    let start = cycle
+        .iter()
+        .position(|program| *program == name)
+        .unwrap_or(0);
+    let mut cycle: Vec<String> = cycle[start..].iter().map(|name| name.to_string()).collect();
+    cycle.push(name.clone());
+    Err(cycle)
+}
+
+pub fn is_auto_log(value: &Option<String>) -> bool {
+    value
+        .as_deref()
+        .is_none_or(|value| value.eq_ignore_ascii_case(AUTO_LOG))
+}
+
+fn validate_configuration(configuration: &Configuration) -> Result<(), ValidationError> {
+    validate_interpolation(configuration)?;
+    validate_command_line(configuration)?;
+    validate_identity(configuration)?;
+    validate_rlimits(configuration)?;
+    validate_redirect_stderr(configuration)?;
+    validate_backoff(configuration)?;
+    validate_health_check(configuration)
+}
+
+fn validate_interpolation(configuration: &Configuration) -> Result<(), ValidationError> {
+    configuration
+        .expand(VALIDATION_PROGRAM_NAME, 0)
+        .map(|_| ())
+        .map_err(|err| {
+            let mut error = ValidationError::new("Invalid interpolation");
+            error.message = Some(Cow::from(err));
+            error
+        })
+}
+
+fn validate_command_line(configuration: &Configuration) -> Result<(), ValidationError> {
+    if configuration.cmd.is_empty() {
+        return Ok(());
+    }
+    let Ok(configuration) = configuration.expand(VALIDATION_PROGRAM_NAME, 0) else {
+        return Ok(());
+    };
+    configuration.command_line().map(|_| ()).map_err(|err| {
+        let mut error = ValidationError::new("Invalid cmd");
+        error.message = Some(Cow::from(format!("cmd: {err}")));
+        error
+    })
+}
+
+fn validate_identity(configuration: &Configuration) -> Result<(), ValidationError> {
+    Identity::resolve(configuration).map(|_| ()).map_err(|err| {
+        let mut error = ValidationError::new("Invalid identity");
+        error.message = Some(Cow::from(err));
+        error
+    })
+}
+
+fn validate_rlimits(configuration: &Configuration) -> Result<(), ValidationError> {
+    for (resource, rlimit) in &configuration.rlimits {
+        rlimit.validate(*resource).map_err(|err| {
+            let mut error = ValidationError::new("Invalid rlimit");
+            error.message = Some(Cow::from(err));
+            error
+        })?;
+    }
+    Ok(())
+}
+
+fn validate_redirect_stderr(configuration: &Configuration) -> Result<(), ValidationError> {
+    if configuration.redirect_stderr && configuration.stderr.is_some() {
+        let mut error = ValidationError::new("Invalid redirect_stderr");
+        error.message = Some(Cow::from(
+            "redirect_stderr: stderr is merged into stdout, remove the stderr option",
+        ));
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_backoff(configuration: &Configuration) -> Result<(), ValidationError> {
+    if configuration.backoff_initial > configuration.backoff_max {
+        let mut error = ValidationError::new("Invalid backoff");
+        error.message = Some(Cow::from(
+            "backoff_max: should be greater than or equal to backoff_initial",
+        ));
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_health_check(configuration: &Configuration) -> Result<(), ValidationError> {
+    if let Some(health_check) = &configuration.health_check {
+        health_check.validate().map_err(|err| {
+            let mut error = ValidationError::new("Invalid health_check");
+            error.message = Some(Cow::from(err));
+            error
+        })?;
+    }
+    Ok(())
+}
+
+fn validate_cpu_max(value: &String) -> Result<(), ValidationError> {
+    let mut parts = value.split_whitespace();
+    let quota_is_valid = match parts.next() {
+        Some("max") => true,
+        Some(quota) => quota.parse::<u64>().is_ok_and(|quota| quota > 0),
+        None => false,
+    };
+    let period_is_valid = match parts.next() {
+        Some(period) => period.parse::<u64>().is_ok_and(|period| period > 0),
+        None => true,
+    };
+    if !quota_is_valid || !period_is_valid || parts.next().is_some() {
+        let mut error = ValidationError::new("Invalid cpu_max");
+        error.message = Some(Cow::from(format!(
+            "cpu_max: \"{value}\" should be \"<quota|max> [period]\" in microseconds"
+        )));
+        return Err(error);
+    }
+    Ok(())
 }
 
 fn validate_umask(value: u32) -> Result<(), ValidationError> {
@@ -241,15 +902,89 @@ fn deserialize_umask<'de, D>(deserializer: D) -> Result<u32, D::Error>
 where
     D: Deserializer<'de>,
 {
-    let value = String::deserialize(deserializer)?;
-    match u32::from_str_radix(value.as_str(), 8) {
-        Ok(umask) => Ok(umask),
-        Err(_) => Err(serde::de::Error::custom(format!(
-            "\"{value}\" is not a valid umask."
+    let value = deserialize_octal(deserializer)?;
+    parse_umask(&value).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_option_bytes<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Bytes {
+        Number(u64),
+        Text(String),
+    }
+
+    let value = match Bytes::deserialize(deserializer)? {
+        Bytes::Number(bytes) => return Ok(Some(bytes)),
+        Bytes::Text(value) => value.trim().to_string(),
+    };
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((idx, 'K' | 'k')) => (&value[..idx], 1 << 10),
+        Some((idx, 'M' | 'm')) => (&value[..idx], 1 << 20),
+        Some((idx, 'G' | 'g')) => (&value[..idx], 1 << 30),
+        _ => (value.as_str(), 1),
+    };
+    match number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+    {
+        Some(bytes) => Ok(Some(bytes)),
+        None => Err(serde::de::Error::custom(format!(
+            "\"{value}\" is not a valid size, expected bytes or a K, M, G suffix"
+        ))),
+    }
+}
+
+fn deserialize_restart_limit<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Limit {
+        Number(u32),
+        Keyword(String),
+    }
+
+    match Limit::deserialize(deserializer)? {
+        Limit::Number(limit) => Ok(Some(limit)),
+        Limit::Keyword(keyword) if keyword.trim() == "unlimited" => Ok(None),
+        Limit::Keyword(keyword) => Err(serde::de::Error::custom(format!(
+            "\"{keyword}\" is not a valid limit, expected a number or \"unlimited\""
         ))),
     }
 }
 
+fn deserialize_shell<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Shell {
+        Enabled(bool),
+        Path(String),
+    }
+
+    match Shell::deserialize(deserializer)? {
+        Shell::Enabled(true) => Ok(Some(DEFAULT_SHELL.to_string())),
+        Shell::Enabled(false) => Ok(None),
+        Shell::Path(path) => {
+            let path = path.trim();
+            if path.is_empty() {
+                Err(serde::de::Error::custom("shell: path can't be empty"))
+            } else {
+                Ok(Some(path.to_string()))
+            }
+        }
+    }
+}
+
 fn deserialize_string_and_trim<'de, D>(deserializer: D) -> Result<String, D::Error>
 where
     D: Deserializer<'de>,
@@ -274,3 +1009,105 @@ where
         Ok(Some(trimmed))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(depends_on: &[&str], priority: u32) -> Configuration {
+        Configuration {
+            depends_on: depends_on.iter().map(|name| name.to_string()).collect(),
+            priority,
+            ..Configuration::default()
+        }
+    }
+
+    fn programs(list: &[(&str, Configuration)]) -> BTreeMap<String, Configuration> {
+        list.iter()
+            .map(|(name, configuration)| (name.to_string(), configuration.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn orders_by_dependencies_then_priority() {
+        let programs = programs(&[
+            ("web", program(&["db", "cache"], 1)),
+            ("db", program(&[], 10)),
+            ("cache", program(&[], 5)),
+            ("cron", program(&["missing"], 999)),
+        ]);
+        assert_eq!(
+            start_order(&programs).unwrap(),
+            ["cache", "db", "web", "cron"]
+        );
+    }
+
+    #[test]
+    fn reports_dependency_cycles() {
+        let programs = programs(&[
+            ("a", program(&["b"], 1)),
+            ("b", program(&["c"], 1)),
+            ("c", program(&["a"], 1)),
+            ("d", program(&[], 1)),
+        ]);
+        assert_eq!(start_order(&programs).unwrap_err(), ["a", "b", "c", "a"]);
+    }
+
+    #[test]
+    fn validates_cpu_max() {
+        for value in ["max", "50000", "50000 100000", "max 100000"] {
+            assert!(validate_cpu_max(&value.to_string()).is_ok(), "{value}");
+        }
+        for value in ["", "0", "half", "50000 0", "50000 100000 1"] {
+            assert!(validate_cpu_max(&value.to_string()).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn parses_restart_limits() {
+        let parse = |yaml: &str| serde_yaml::from_str::<Configuration>(yaml);
+        assert_eq!(parse("cmd: x").unwrap().max_restarts_per_window, None);
+        assert_eq!(
+            parse("max_restarts_per_window: 2")
+                .unwrap()
+                .max_restarts_per_window,
+            Some(2)
+        );
+        assert_eq!(
+            parse("max_restarts_per_window: unlimited")
+                .unwrap()
+                .max_restarts_per_window,
+            None
+        );
+        assert!(parse("max_restarts_per_window: often").is_err());
+    }
+
+    #[test]
+    fn signals_only_the_program_by_default() {
+        let parse = |yaml: &str| serde_yaml::from_str::<Configuration>(yaml).unwrap();
+        let configuration = parse("cmd: x");
+        assert!(!configuration.stopasgroup);
+        assert!(!configuration.killasgroup);
+        assert!(parse("killasgroup: true").killasgroup);
+    }
+
+    #[test]
+    fn numbers_only_automatic_log_paths() {
+        let configuration = Configuration {
+            num_procs: 2,
+            stdout: Some("/dev/null".to_string()),
+            stderr: Some("/tmp/web-%(process_num)d.log".to_string()),
+            ..Configuration::default()
+        };
+        let instance = configuration.expand("web", 1).unwrap();
+        assert_eq!(instance.stdout.as_deref(), Some("/dev/null"));
+        assert_eq!(instance.stderr.as_deref(), Some("/tmp/web-1.log"));
+        let instance = Configuration {
+            stdout: None,
+            ..configuration
+        }
+        .expand("web", 1)
+        .unwrap();
+        assert!(instance.stdout.unwrap().ends_with("/web-1-stdout.log"));
+    }
+}
@@ -0,0 +1,541 @@
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess,
+    Visitor,
+};
+use serde::Serialize;
+use serde_yaml::{Mapping, Value};
+use std::cell::Cell;
+use std::fmt::{Display, Formatter};
+
+#[derive(Debug, Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+pub struct Source<'a> {
+    content: &'a str,
+}
+
+pub struct Diagnostics<'a> {
+    source: Source<'a>,
+    file: Option<String>,
+    errors: Vec<String>,
+}
+
+struct Locator<'p> {
+    path: &'p [&'p str],
+    found: &'p Cell<bool>,
+}
+
+struct Key<'p> {
+    name: &'p str,
+    is_last: bool,
+    found: &'p Cell<bool>,
+}
+
+impl<'de, 'p> DeserializeSeed<'de> for Locator<'p> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de, 'p> Visitor<'de> for Locator<'p> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Some((name, rest)) = self.path.split_first() else {
+            while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+            return Ok(());
+        };
+        let key = || Key {
+            name,
+            is_last: rest.is_empty(),
+            found: self.found,
+        };
+        while let Some(matched) = map.next_key_seed(key())? {
+            match matched {
+                true => map.next_value_seed(Locator {
+                    path: rest,
+                    found: self.found,
+                })?,
+                false => map.next_value::<IgnoredAny>().map(|_| ())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'de, 'p> DeserializeSeed<'de> for Key<'p> {
+    type Value = bool;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<bool, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'p> Key<'p> {
+    fn matches<E: de::Error>(self, key: &str) -> Result<bool, E> {
+        match key == self.name {
+            true if self.is_last => {
+                self.found.set(true);
+                Err(E::custom("key found"))
+            }
+            matched => Ok(matched),
+        }
+    }
+}
+
+impl<'de, 'p> Visitor<'de> for Key<'p> {
+    type Value = bool;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("a key")
+    }
+
+    fn visit_bool<E: de::Error>(self, key: bool) -> Result<bool, E> {
+        self.matches(&key.to_string())
+    }
+
+    fn visit_i64<E: de::Error>(self, key: i64) -> Result<bool, E> {
+        self.matches(&key.to_string())
+    }
+
+    fn visit_u64<E: de::Error>(self, key: u64) -> Result<bool, E> {
+        self.matches(&key.to_string())
+    }
+
+    fn visit_f64<E: de::Error>(self, key: f64) -> Result<bool, E> {
+        self.matches(&key.to_string())
+    }
+
+    fn visit_str<E: de::Error>(self, key: &str) -> Result<bool, E> {
+        self.matches(key)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<bool, E> {
+        Ok(false)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<bool, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(false)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<bool, A::Error> {
+        while map.next_entry::<IgnoredAny, IgnoredAny>()?.is_some() {}
+        Ok(false)
+    }
+}
+
+pub fn field_names<T: Serialize + Default>() -> Vec<String> {
+    match serde_yaml::to_value(T::default()) {
+        Ok(Value::Mapping(mapping)) => mapping.keys().map(key_name).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn edit_distance(first: &str, second: &str) -> usize {
+    let second: Vec<char> = second.chars().collect();
+    let mut previous: Vec<usize> = (0..=second.len()).collect();
+    for (i, a) in first.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b) in second.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a != *b);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[second.len()]
+}
+
+pub fn suggest<'a>(key: &str, candidates: &[&'a str]) -> Option<&'a str> {
+    let normalized = key.to_lowercase().replace(['-', ' '], "_");
+    candidates
+        .iter()
+        .map(|candidate| {
+            let distance = edit_distance(&normalized, candidate).min(edit_distance(
+                &normalized.replace('_', ""),
+                &candidate.replace('_', ""),
+            ));
+            (distance, *candidate)
+        })
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(1))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+pub fn unknown_key_message(key: &str, candidates: &[&str]) -> String {
+    match suggest(key, candidates) {
+        Some(suggestion) => format!("unknown key \"{key}\", did you mean \"{suggestion}\"?"),
+        None => format!(
+            "unknown key \"{key}\", expected one of: {}",
+            candidates.join(", ")
+        ),
+    }
+}
+
+pub fn key_name(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        Value::Number(number) => number.to_string(),
+        Value::Bool(boolean) => boolean.to_string(),
+        key => format!("{key:?}"),
+    }
+}
+
+pub fn unknown_keys(mapping: &Mapping, candidates: &[&str]) -> Vec<String> {
+    mapping
+        .keys()
+        .map(key_name)
+        .filter(|key| !candidates.contains(&key.as_str()))
+        .collect()
+}
+
+fn remove_yaml_nulls(value: &mut Value) {
+    match value {
+        Value::Mapping(mapping) => {
+            mapping.retain(|_, value| !value.is_null());
+            mapping.values_mut().for_each(remove_yaml_nulls);
+        }
+        Value::Sequence(sequence) => sequence.iter_mut().for_each(remove_yaml_nulls),
+        _ => {}
+    }
+}
+
+pub fn from_yaml_value<T: DeserializeOwned>(mut value: Value) -> Result<T, String> {
+    remove_yaml_nulls(&mut value);
+    let content = serde_yaml::to_string(&value).map_err(|err| err.to_string())?;
+    serde_yaml::from_str(&content).map_err(|err| {
+        let message = err.to_string();
+        match err.location() {
+            Some(location) => message
+                .trim_end_matches(&format!(
+                    " at line {} column {}",
+                    location.line(),
+                    location.column()
+                ))
+                .to_string(),
+            None => message,
+        }
+    })
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {} column {}", self.line, self.column)
+    }
+}
+
+impl<'a> Source<'a> {
+    pub fn new(content: &'a str) -> Source<'a> {
+        Source { content }
+    }
+
+    pub fn locate(&self, path: &[&str]) -> Option<Position> {
+        if path.is_empty() {
+            return None;
+        }
+        let found = Cell::new(false);
+        let error = Locator {
+            path,
+            found: &found,
+        }
+        .deserialize(serde_yaml::Deserializer::from_str(self.content))
+        .err()?;
+        if !found.get() {
+            return None;
+        }
+        error.location().map(|location| Position {
+            line: location.line(),
+            column: location.column(),
+        })
+    }
+}
+
+impl<'a> Diagnostics<'a> {
+    pub fn new(content: &'a str) -> Diagnostics<'a> {
+        Diagnostics {
+            source: Source::new(content),
+            file: None,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn with_file(content: &'a str, file: String) -> Diagnostics<'a> {
+        Diagnostics {
+            source: Source::new(content),
+            file: Some(file),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn absorb(&mut self, other: Diagnostics) {
+        self.errors.extend(other.errors);
+    }
+
+    pub fn locate(&self, path: &[&str]) -> Option<Position> {
+        self.source.locate(path)
+    }
+
+    pub fn error<M: Display>(&mut self, context: &str, position: Option<Position>, message: M) {
+        let file = match &self.file {
+            Some(file) => format!("{file}: "),
+            None => String::new(),
+        };
+        self.errors.push(match position {
+            Some(position) => {
+                format!("Configuration error: {file}{context}: {position}: {message}")
+            }
+            None => format!("Configuration error: {file}{context}: {message}"),
+        });
+    }
+
+    pub fn mapping(&mut self, context: &str, path: &[&str], value: Value) -> Option<Mapping> {
+        match value {
+            Value::Null => Some(Mapping::new()),
+            Value::Mapping(mapping) => Some(mapping),
+            _ => {
+                let position = self.locate(path);
+                self.error(context, position, "expected a mapping");
+                None
+            }
+        }
+    }
+
+    pub fn remove_unknown_keys<T: Serialize + Default>(
+        &mut self,
+        context: &str,
+        path: &[&str],
+        mut mapping: Mapping,
+    ) -> Mapping {
+        let fields = field_names::<T>();
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+        for key in unknown_keys(&mapping, &fields) {
+            let position = self.locate(&[path, &[key.as_str()]].concat());
+            self.error(context, position, unknown_key_message(&key, &fields));
+        }
+        mapping.retain(|key, _| fields.contains(&key_name(key).as_str()));
+        mapping
+    }
+
+    pub fn locate_key(&self, paths: &[&[&str]], key: Option<&str>) -> Option<Position> {
+        paths.iter().find_map(|path| match key {
+            Some(key) => self.locate(&[*path, &[key]].concat()),
+            None => self.locate(path),
+        })
+    }
+
+    pub fn locate_message(&self, paths: &[&[&str]], message: &str) -> Option<Position> {
+        let fields: Vec<&str> = message
+            .split(": ")
+            .take_while(|segment| !segment.is_empty() && !segment.contains(char::is_whitespace))
+            .flat_map(|segment| segment.split('.'))
+            .collect();
+        (1..=fields.len())
+            .rev()
+            .find_map(|length| {
+                paths
+                    .iter()
+                    .find_map(|path| self.locate(&[*path, &fields[..length]].concat()))
+            })
+            .or_else(|| self.locate_key(paths, None))
+    }
+
+    pub fn deserialize<T: DeserializeOwned>(
+        &mut self,
+        context: &str,
+        paths: &[&[&str]],
+        mut mapping: Mapping,
+    ) -> (Option<T>, Mapping) {
+        let err = match from_yaml_value(Value::Mapping(mapping.clone())) {
+            Ok(value) => return (Some(value), mapping),
+            Err(err) => err,
+        };
+        let mut invalid_keys = Vec::new();
+        for (key, value) in &mapping {
+            let single = Mapping::from_iter([(key.clone(), value.clone())]);
+            if let Err(message) = from_yaml_value::<T>(Value::Mapping(single)) {
+                let key = key_name(key);
+                let position = self.locate_key(paths, Some(&key));
+                match message.starts_with(key.as_str()) {
+                    true => self.error(context, position, message),
+                    false => self.error(context, position, format!("{key}: {message}")),
+                }
+                invalid_keys.push(key);
+            }
+        }
+        if invalid_keys.is_empty() {
+            let position = self.locate_key(paths, None);
+            self.error(context, position, err);
+        }
+        mapping.retain(|key, _| !invalid_keys.contains(&key_name(key)));
+        (None, mapping)
+    }
+
+    pub fn parse_section<T: DeserializeOwned + Serialize + Default>(
+        &mut self,
+        context: &str,
+        path: &[&str],
+        value: Value,
+    ) -> Option<T> {
+        let mapping = self.mapping(context, path, value)?;
+        let mapping = self.remove_unknown_keys::<T>(context, path, mapping);
+        self.deserialize(context, &[path], mapping).0
+    }
+
+    pub fn into_result<T>(self, value: T) -> Result<T, String> {
+        if self.errors.is_empty() {
+            Ok(value)
+        } else {
+            Err(self.errors.join("\n"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::Configuration;
+    use crate::settings::Settings;
+
+    const CONTENT: &str = "\
+# comment
+defaults:
+  stop_time: 3
+programs:
+  web:
+    cmd: \"true\"
+    env:
+      PORT: \"80\"
+  \"quoted\":
+    cmd: \"false\"
+  flow: {cmd: \"true\", num_procs: 2}
+  8080:
+    cmd: \"true\"
+";
+
+    fn position(line: usize, column: usize) -> Option<(usize, usize)> {
+        Some((line, column))
+    }
+
+    fn locate(path: &[&str]) -> Option<(usize, usize)> {
+        Source::new(CONTENT)
+            .locate(path)
+            .map(|position| (position.line, position.column))
+    }
+
+    #[test]
+    fn locates_nested_keys() {
+        assert_eq!(locate(&["defaults"]), position(2, 1));
+        assert_eq!(locate(&["defaults", "stop_time"]), position(3, 3));
+        assert_eq!(locate(&["programs", "web", "env", "PORT"]), position(8, 7));
+    }
+
+    #[test]
+    fn locates_quoted_flow_and_number_keys() {
+        assert_eq!(locate(&["programs", "quoted", "cmd"]), position(10, 5));
+        assert_eq!(locate(&["programs", "flow", "num_procs"]), position(11, 23));
+        assert_eq!(locate(&["programs", "8080", "cmd"]), position(13, 5));
+    }
+
+    #[test]
+    fn missing_keys_have_no_position() {
+        assert_eq!(locate(&[]), None);
+        assert_eq!(locate(&["web"]), None);
+        assert_eq!(locate(&["programs", "web", "user"]), None);
+        assert_eq!(locate(&["programs", "web", "cmd", "true"]), None);
+    }
+
+    #[test]
+    fn locates_fields_named_in_messages() {
+        let diagnostics = Diagnostics::new(CONTENT);
+        let paths: [&[&str]; 2] = [&["programs", "web"], &["defaults"]];
+        let locate = |message: &str| {
+            diagnostics
+                .locate_message(&paths, message)
+                .map(|position| (position.line, position.column))
+        };
+        assert_eq!(locate("env.PORT: invalid"), position(8, 7));
+        assert_eq!(locate("env.HOST: invalid"), position(7, 5));
+        assert_eq!(locate("stop_time: too long"), position(3, 3));
+        assert_eq!(locate("cmd: unterminated single quote"), position(6, 5));
+        assert_eq!(locate("no field here"), position(5, 3));
+    }
+
+    #[test]
+    fn reports_errors_with_positions() {
+        let mut diagnostics = Diagnostics::with_file(CONTENT, "main.yml".to_string());
+        let mut program = Mapping::new();
+        program.insert("cmd".into(), "true".into());
+        program.insert("autostart".into(), true.into());
+        program.insert("num_procs".into(), "many".into());
+        let mapping =
+            diagnostics.remove_unknown_keys::<Configuration>("web", &["programs", "web"], program);
+        let (configuration, _) =
+            diagnostics.deserialize::<Configuration>("web", &[&["programs", "web"]], mapping);
+        assert!(configuration.is_none());
+        let errors = diagnostics.into_result(()).unwrap_err();
+        let errors: Vec<&str> = errors.lines().collect();
+        assert_eq!(
+            errors[0],
+            "Configuration error: main.yml: web: unknown key \"autostart\", did you mean \"auto_start\"?"
+        );
+        assert!(errors[1].starts_with("Configuration error: main.yml: web: num_procs: "));
+    }
+
+    #[test]
+    fn lists_struct_fields() {
+        let fields = field_names::<Configuration>();
+        assert!(fields.iter().any(|field| field == "cmd"));
+        assert!(fields.iter().any(|field| field == "health_check"));
+        assert!(field_names::<Settings>().contains(&"socket_mode".to_string()));
+    }
+
+    #[test]
+    fn suggests_close_keys() {
+        let candidates = ["auto_start", "auto_restart", "stop_time"];
+        assert_eq!(suggest("autostart", &candidates), Some("auto_start"));
+        assert_eq!(suggest("Stop-Time", &candidates), Some("stop_time"));
+        assert_eq!(suggest("command", &candidates), None);
+    }
+}
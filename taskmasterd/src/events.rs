@@ -0,0 +1,199 @@
+use crate::remove_and_exit;
+use libc::{c_int, c_void, pid_t};
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::SystemTime;
+
+static SIGCHLD_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle_sigchld(_: c_int) {
+    let fd = SIGCHLD_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        unsafe {
+            let saved_errno = *libc::__errno_location();
+            libc::write(fd, [1u8].as_ptr() as *const c_void, 1);
+            *libc::__errno_location() = saved_errno;
+        }
+    }
+}
+
+fn create_pipe() -> (RawFd, RawFd) {
+    let mut fds = [-1 as RawFd; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } == -1 {
+        eprintln!(
+            "Error creating the monitor pipe: {}",
+            std::io::Error::last_os_error()
+        );
+        remove_and_exit(1);
+    }
+    (fds[0], fds[1])
+}
+
+fn drain(fd: RawFd) {
+    let mut buffer = [0u8; 64];
+    while unsafe { libc::read(fd, buffer.as_mut_ptr() as *mut c_void, buffer.len()) } > 0 {}
+}
+
+pub struct Events {
+    sigchld_read: RawFd,
+    wake_read: RawFd,
+    wake_write: RawFd,
+    exits: Mutex<HashMap<pid_t, ExitStatus>>,
+}
+
+impl Events {
+    pub fn new() -> Events {
+        let (sigchld_read, sigchld_write) = create_pipe();
+        let (wake_read, wake_write) = create_pipe();
+        SIGCHLD_PIPE.store(sigchld_write, Ordering::Relaxed);
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_sigchld as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART | libc::SA_NOCLDSTOP;
+            libc::sigemptyset(&mut action.sa_mask);
+            if libc::sigaction(libc::SIGCHLD, &action, std::ptr::null_mut()) == -1 {
+                eprintln!("Error setting up signal handler for SIGCHLD");
+                remove_and_exit(1);
+            }
+        }
+        Events {
+            sigchld_read,
+            wake_read,
+            wake_write,
+            exits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn reap(&self) -> MutexGuard<'_, HashMap<pid_t, ExitStatus>> {
+        let mut exits = self.exits.lock().unwrap();
+        loop {
+            let mut status = 0;
+            let pid = unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) };
+            if pid <= 0 {
+                return exits;
+            }
+            exits.insert(pid, ExitStatus::from_raw(status));
+        }
+    }
+
+    pub fn try_wait(&self, child: &mut Child) -> io::Result<Option<ExitStatus>> {
+        let mut exits = self.exits.lock().unwrap();
+        match exits.remove(&(child.id() as pid_t)) {
+            Some(status) => Ok(Some(status)),
+            None => child.try_wait(),
+        }
+    }
+
+    pub fn kill(&self, child: &mut Child) -> io::Result<()> {
+        let exits = self.exits.lock().unwrap();
+        match exits.contains_key(&(child.id() as pid_t)) {
+            true => Ok(()),
+            false => child.kill(),
+        }
+    }
+
+    pub fn wake(&self) {
+        unsafe {
+            libc::write(self.wake_write, [1u8].as_ptr() as *const c_void, 1);
+        }
+    }
+
+    pub fn wait(&self, deadline: Option<SystemTime>) {
+        let timeout = match deadline {
+            None => -1,
+            Some(deadline) => match deadline.duration_since(SystemTime::now()) {
+                Ok(remaining) => (remaining.as_millis() + 1).min(c_int::MAX as u128) as c_int,
+                Err(_) => 0,
+            },
+        };
+        let mut fds = [
+            libc::pollfd {
+                fd: self.sigchld_read,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: self.wake_read,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        unsafe {
+            libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout);
+        }
+        drain(self.sigchld_read);
+        drain(self.wake_read);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn waits_until_the_deadline() {
+        let events = Events::new();
+        let started = Instant::now();
+        events.wait(Some(SystemTime::now() + Duration::from_millis(200)));
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200));
+        assert!(elapsed < Duration::from_secs(2));
+
+        let started = Instant::now();
+        events.wait(Some(SystemTime::now() - Duration::from_secs(1)));
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn wakes_up_the_waiting_thread() {
+        let events = Arc::new(Events::new());
+        let waker = events.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            waker.wake();
+        });
+        let started = Instant::now();
+        events.wait(None);
+        assert!(started.elapsed() < Duration::from_secs(5));
+        handle.join().unwrap();
+
+        events.wake();
+        events.wake();
+        events.wait(Some(SystemTime::now()));
+        let started = Instant::now();
+        events.wait(Some(SystemTime::now() + Duration::from_millis(200)));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn reaps_exited_children() {
+        let events = Events::new();
+        let mut child = Command::new("sh").args(["-c", "exit 3"]).spawn().unwrap();
+        let pid = child.id() as pid_t;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let status = loop {
+            events.wait(Some(SystemTime::now() + Duration::from_millis(100)));
+            if let Some(status) = events.reap().remove(&pid) {
+                break status;
+            }
+            assert!(Instant::now() < deadline, "child was not reaped");
+        };
+        assert_eq!(status.code(), Some(3));
+        events.reap().insert(pid, status);
+        assert!(events.kill(&mut child).is_ok());
+        assert_eq!(
+            events.try_wait(&mut child).unwrap().unwrap().code(),
+            Some(3)
+        );
+        assert!(!events.reap().contains_key(&pid));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::configuration::State;
+use libc::c_int;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const SIGNAL_PREFIX: &str = "SIG";
+const MAX_SIGNAL: c_int = 64;
+
+const SIGNALS: &[(&str, c_int)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+    ("PWR", libc::SIGPWR),
+    ("STKFLT", libc::SIGSTKFLT),
+];
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct ExitSignal(pub c_int);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ExitRecord {
+    pub exited_at: u64,
+    pub code: Option<i32>,
+    pub signal: Option<String>,
+    pub core_dumped: bool,
+    pub expected: bool,
+}
+
+pub fn signal_name(signum: c_int) -> String {
+    match SIGNALS.iter().find(|(_, number)| *number == signum) {
+        Some((name, _)) => name.to_string(),
+        None => signum.to_string(),
+    }
+}
+
+fn parse_signal(value: &str) -> Result<c_int, String> {
+    let name = value.trim().to_ascii_uppercase();
+    let name = name.strip_prefix(SIGNAL_PREFIX).unwrap_or(&name);
+    if let Some((_, signum)) = SIGNALS.iter().find(|(known, _)| *known == name) {
+        return Ok(*signum);
+    }
+    match name.parse::<c_int>() {
+        Ok(signum) if (1..=MAX_SIGNAL).contains(&signum) => Ok(signum),
+        _ => Err(format!("\"{value}\" is not a valid signal")),
+    }
+}
+
+impl<'de> Deserialize<'de> for ExitSignal {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Signal {
+            Number(i64),
+            Name(String),
+        }
+
+        let value = match Signal::deserialize(deserializer)? {
+            Signal::Number(signum) => signum.to_string(),
+            Signal::Name(name) => name,
+        };
+        parse_signal(&value)
+            .map(ExitSignal)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for ExitSignal {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&signal_name(self.0))
+    }
+}
+
+impl ExitRecord {
+    pub fn new(status: &ExitStatus, exit_codes: &[i32], exit_signals: &[ExitSignal]) -> ExitRecord {
+        let code = status.code();
+        let signal = status.signal();
+        ExitRecord {
+            exited_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::from_secs(0))
+                .as_secs(),
+            code,
+            signal: signal.map(signal_name),
+            core_dumped: status.core_dumped(),
+            expected: code.is_some_and(|code| exit_codes.contains(&code))
+                || signal.is_some_and(|signum| exit_signals.contains(&ExitSignal(signum))),
+        }
+    }
+
+    pub fn at(&self) -> String {
+        State::at(&(UNIX_EPOCH + Duration::from_secs(self.exited_at)))
+    }
+}
+
+impl Display for ExitRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (&self.signal, self.code) {
+            (Some(signal), _) if self.core_dumped => write!(f, "signal {signal}, core dumped"),
+            (Some(signal), _) => write!(f, "signal {signal}"),
+            (None, Some(code)) => write!(f, "code {code}"),
+            (None, None) => write!(f, "unknown status"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_signal_names_and_numbers() {
+        assert_eq!(parse_signal("TERM"), Ok(libc::SIGTERM));
+        assert_eq!(parse_signal(" sigpwr "), Ok(libc::SIGPWR));
+        assert_eq!(parse_signal("STKFLT"), Ok(libc::SIGSTKFLT));
+        assert_eq!(parse_signal("34"), Ok(34));
+        assert!(parse_signal("0").is_err());
+        assert!(parse_signal("65").is_err());
+        assert!(parse_signal("SIGNOPE").is_err());
+        assert_eq!(signal_name(libc::SIGPWR), "PWR");
+        assert_eq!(signal_name(40), "40");
+    }
+
+    #[test]
+    fn classifies_exits() {
+        let signals = [ExitSignal(libc::SIGTERM)];
+        let record = |raw: i32| ExitRecord::new(&ExitStatus::from_raw(raw), &[0, 2], &signals);
+        let exited = record(2 << 8);
+        assert_eq!((exited.code, exited.expected), (Some(2), true));
+        assert_eq!(exited.to_string(), "code 2");
+        assert!(!record(1 << 8).expected);
+        let terminated = record(libc::SIGTERM);
+        assert_eq!(terminated.signal.as_deref(), Some("TERM"));
+        assert!(terminated.expected);
+        let dumped = record(libc::SIGSEGV | 0x80);
+        assert!(!dumped.expected);
+        assert_eq!(dumped.to_string(), "signal SEGV, core dumped");
+    }
+}
@@ -0,0 +1,433 @@
+use crate::command_line::DEFAULT_SHELL;
+use crate::configuration::Configuration;
+use crate::events::Events;
+use crate::identity::Identity;
+use crate::interpolation::Mode;
+use crate::utils::{connect_tcp, connect_unix};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const HTTP_SCHEME: &str = "http://";
+const LOCALHOST: &str = "127.0.0.1";
+
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthCheck {
+    pub tcp: Option<String>,
+    pub unix: Option<String>,
+    pub exec: Option<String>,
+    pub http: Option<String>,
+    pub interval: u64,
+    pub timeout: u64,
+    pub failure_threshold: u32,
+    pub restart: bool,
+}
+
+#[derive(Clone)]
+enum Probe {
+    Tcp(String),
+    Unix(String),
+    Exec(String),
+    Http(String, String),
+}
+
+type ProbeResult = Arc<Mutex<Option<Result<(), String>>>>;
+
+pub struct Health {
+    check: HealthCheck,
+    result: ProbeResult,
+    is_probing: bool,
+    next_probe: SystemTime,
+    failures: u32,
+    pub last_error: Option<String>,
+    pub stopped_at: Option<SystemTime>,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            tcp: None,
+            unix: None,
+            exec: None,
+            http: None,
+            interval: 10,
+            timeout: 5,
+            failure_threshold: 3,
+            restart: false,
+        }
+    }
+}
+
+fn parse_http_target(target: &str) -> Result<(String, String), String> {
+    let target = target.strip_prefix(HTTP_SCHEME).unwrap_or(target);
+    let (address, path) = match target.find('/') {
+        Some(slash) => (&target[..slash], &target[slash..]),
+        None => (target, "/"),
+    };
+    let address = match address.parse::<u16>() {
+        Ok(port) => format!("{LOCALHOST}:{port}"),
+        Err(_) if address.contains(':') => address.to_string(),
+        Err(_) => {
+            return Err(format!(
+                "health_check: \"{target}\" should be \"[host:]port[/path]\""
+            ))
+        }
+    };
+    Ok((address, path.to_string()))
+}
+
+impl HealthCheck {
+    fn probe(&self) -> Option<Probe> {
+        if let Some(address) = &self.tcp {
+            return Some(Probe::Tcp(address.clone()));
+        }
+        if let Some(path) = &self.unix {
+            return Some(Probe::Unix(path.clone()));
+        }
+        if let Some(command) = &self.exec {
+            return Some(Probe::Exec(command.clone()));
+        }
+        let (address, path) = parse_http_target(self.http.as_deref()?).ok()?;
+        Some(Probe::Http(address, path))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let probes = [&self.tcp, &self.unix, &self.exec, &self.http];
+        if probes.iter().filter(|probe| probe.is_some()).count() != 1 {
+            return Err("health_check: set exactly one of tcp, unix, exec or http".to_string());
+        }
+        if let Some(http) = &self.http {
+            parse_http_target(http)?;
+        }
+        if self.interval == 0 || self.timeout == 0 || self.failure_threshold == 0 {
+            return Err(
+                "health_check: interval, timeout and failure_threshold should be at least 1"
+                    .to_string(),
+            );
+        }
+        Ok(())
+    }
+
+    pub fn expand<F>(&self, expand: F) -> Result<HealthCheck, String>
+    where
+        F: Fn(&str, &Option<String>, Mode) -> Result<Option<String>, String>,
+    {
+        Ok(HealthCheck {
+            tcp: expand("health_check.tcp", &self.tcp, Mode::Value)?,
+            unix: expand("health_check.unix", &self.unix, Mode::Value)?,
+            exec: expand("health_check.exec", &self.exec, Mode::Command)?,
+            http: expand("health_check.http", &self.http, Mode::Value)?,
+            ..self.clone()
+        })
+    }
+}
+
+fn run_command(
+    command: &str,
+    configuration: &Configuration,
+    identity: Identity,
+    timeout: Duration,
+    events: &Events,
+) -> Result<(), String> {
+    let mut child = unsafe {
+        Command::new(DEFAULT_SHELL)
+            .arg("-c")
+            .arg(command)
+            .current_dir(configuration.working_dir.as_deref().unwrap_or("."))
+            .envs(&configuration.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .pre_exec(move || identity.apply())
+            .spawn()
+            .map_err(|e| format!("can't run \"{command}\": {e}"))?
+    };
+    let deadline = SystemTime::now() + timeout;
+    let mut is_killed = false;
+    loop {
+        match events.try_wait(&mut child) {
+            Ok(Some(_)) if is_killed => return Err(format!("\"{command}\" timed out")),
+            Ok(Some(status)) if status.success() => return Ok(()),
+            Ok(Some(status)) => return Err(format!("\"{command}\" {status}")),
+            Ok(None) if !is_killed && SystemTime::now() >= deadline => {
+                let _ = events.kill(&mut child);
+                is_killed = true;
+            }
+            Ok(None) => thread::sleep(EXEC_POLL_INTERVAL),
+            Err(e) => return Err(format!("can't wait for \"{command}\": {e}")),
+        }
+    }
+}
+
+fn http_get(address: &str, path: &str, timeout: Duration) -> Result<(), String> {
+    let mut stream = connect_tcp(address, timeout)?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .and_then(|_| stream.set_write_timeout(Some(timeout)))
+        .and_then(|_| {
+            stream.write_all(
+                format!("GET {path} HTTP/1.0\r\nHost: {address}\r\nConnection: close\r\n\r\n")
+                    .as_bytes(),
+            )
+        })
+        .map_err(|e| format!("can't send the request to {address}: {e}"))?;
+    let mut response = Vec::new();
+    let mut buffer = [0u8; 256];
+    while !response.contains(&b'\n') {
+        match stream.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => response.extend_from_slice(&buffer[..read]),
+            Err(e) => return Err(format!("can't read the response of {address}: {e}")),
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    match response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+    {
+        Some(code) if (200..400).contains(&code) => Ok(()),
+        Some(code) => Err(format!("GET {address}{path} returned {code}")),
+        None => Err(format!("GET {address}{path} returned an invalid response")),
+    }
+}
+
+fn run_probe(
+    probe: Probe,
+    configuration: &Configuration,
+    timeout: Duration,
+    events: &Events,
+) -> Result<(), String> {
+    match probe {
+        Probe::Tcp(address) => connect_tcp(&address, timeout).map(|_| ()),
+        Probe::Unix(path) => connect_unix(&path, timeout).map(|_| ()),
+        Probe::Exec(command) => {
+            let identity = Identity::resolve(configuration)?;
+            run_command(&command, configuration, identity, timeout, events)
+        }
+        Probe::Http(address, path) => http_get(&address, &path, timeout),
+    }
+}
+
+impl Health {
+    pub fn new(check: HealthCheck) -> Health {
+        Health {
+            check,
+            result: Arc::new(Mutex::new(None)),
+            is_probing: false,
+            next_probe: SystemTime::now(),
+            failures: 0,
+            last_error: None,
+            stopped_at: None,
+        }
+    }
+
+    pub fn is_failing(&self) -> bool {
+        self.failures >= self.check.failure_threshold
+    }
+
+    pub fn restarts(&self) -> bool {
+        self.check.restart
+    }
+
+    pub fn next_deadline(&self) -> Option<SystemTime> {
+        match self.is_probing {
+            true => self.result.lock().unwrap().is_some().then(SystemTime::now),
+            false => Some(self.next_probe),
+        }
+    }
+
+    pub fn poll(
+        &mut self,
+        configuration: &Configuration,
+        events: &Arc<Events>,
+    ) -> Option<Result<(), String>> {
+        let finished = self.result.lock().unwrap().take();
+        if let Some(result) = &finished {
+            self.is_probing = false;
+            self.next_probe = SystemTime::now() + Duration::from_secs(self.check.interval);
+            match result {
+                Ok(()) => {
+                    self.failures = 0;
+                    self.last_error = None;
+                }
+                Err(err) => {
+                    self.failures += 1;
+                    self.last_error = Some(err.clone());
+                }
+            }
+        }
+        if !self.is_probing && SystemTime::now() >= self.next_probe {
+            if let Some(probe) = self.check.probe() {
+                self.is_probing = true;
+                let result = self.result.clone();
+                let configuration = configuration.clone();
+                let timeout = Duration::from_secs(self.check.timeout);
+                let events = events.clone();
+                thread::spawn(move || {
+                    *result.lock().unwrap() =
+                        Some(run_probe(probe, &configuration, timeout, &events));
+                    events.wake();
+                });
+            }
+        }
+        finished
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::os::unix::net::UnixListener;
+
+    fn check(probe: HealthCheck) -> HealthCheck {
+        HealthCheck {
+            interval: 1,
+            timeout: 1,
+            failure_threshold: 2,
+            ..probe
+        }
+    }
+
+    fn tcp(address: &str) -> HealthCheck {
+        check(HealthCheck {
+            tcp: Some(address.to_string()),
+            ..HealthCheck::default()
+        })
+    }
+
+    #[test]
+    fn parses_http_targets() {
+        let target = |address: &str, path: &str| Ok((address.to_string(), path.to_string()));
+        assert_eq!(parse_http_target("8080"), target("127.0.0.1:8080", "/"));
+        assert_eq!(
+            parse_http_target("http://8080/health"),
+            target("127.0.0.1:8080", "/health")
+        );
+        assert_eq!(
+            parse_http_target("db.local:5432/ready?full=1"),
+            target("db.local:5432", "/ready?full=1")
+        );
+        assert!(parse_http_target("db.local/ready").is_err());
+        assert!(parse_http_target("http://").is_err());
+    }
+
+    #[test]
+    fn validates_checks() {
+        assert_eq!(tcp("127.0.0.1:80").validate(), Ok(()));
+        assert!(HealthCheck::default().validate().is_err());
+        assert!(HealthCheck {
+            exec: Some("true".to_string()),
+            ..tcp("127.0.0.1:80")
+        }
+        .validate()
+        .is_err());
+        assert!(HealthCheck {
+            http: Some("localhost".to_string()),
+            ..HealthCheck::default()
+        }
+        .validate()
+        .is_err());
+        for zero in [
+            HealthCheck {
+                interval: 0,
+                ..tcp("127.0.0.1:80")
+            },
+            HealthCheck {
+                timeout: 0,
+                ..tcp("127.0.0.1:80")
+            },
+            HealthCheck {
+                failure_threshold: 0,
+                ..tcp("127.0.0.1:80")
+            },
+        ] {
+            assert!(zero.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn fails_after_consecutive_failures() {
+        let events = Arc::new(Events::new());
+        let configuration = Configuration::default();
+        let mut health = Health::new(tcp("127.0.0.1:80"));
+        health.next_probe = SystemTime::now() + Duration::from_secs(3600);
+        let poll = |health: &mut Health, result: Result<(), String>| {
+            health.is_probing = true;
+            *health.result.lock().unwrap() = Some(result.clone());
+            assert!(health.next_deadline().unwrap() <= SystemTime::now());
+            assert_eq!(health.poll(&configuration, &events), Some(result));
+            health.is_failing()
+        };
+        assert!(!poll(&mut health, Err("refused".to_string())));
+        assert!(poll(&mut health, Err("refused".to_string())));
+        assert!(poll(&mut health, Err("refused".to_string())));
+        assert_eq!(health.last_error, Some("refused".to_string()));
+        assert!(!poll(&mut health, Ok(())));
+        assert_eq!(health.last_error, None);
+        assert!(!poll(&mut health, Err("refused".to_string())));
+        assert!(!health.is_probing);
+        assert_eq!(health.poll(&configuration, &events), None);
+    }
+
+    #[test]
+    fn probes_in_the_background() {
+        let events = Arc::new(Events::new());
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .to_string();
+        let mut health = Health::new(tcp(&address));
+        assert_eq!(health.poll(&Configuration::default(), &events), None);
+        assert!(health.is_probing);
+        let deadline = SystemTime::now() + Duration::from_secs(5);
+        let result = loop {
+            events.wait(Some(SystemTime::now() + Duration::from_millis(100)));
+            if let Some(result) = health.poll(&Configuration::default(), &events) {
+                break result;
+            }
+            assert!(SystemTime::now() < deadline, "the probe didn't finish");
+        };
+        assert!(result
+            .unwrap_err()
+            .starts_with(&format!("can't connect to {address}: ")));
+        assert_eq!(health.failures, 1);
+        assert!(health.next_probe > SystemTime::now());
+    }
+
+    #[test]
+    fn times_out_unix_probes() {
+        let path =
+            std::env::temp_dir().join(format!("taskmaster-probe-{}.sock", std::process::id()));
+        let path = path.display().to_string();
+        let _ = std::fs::remove_file(&path);
+        let _listener = UnixListener::bind(&path).unwrap();
+        let mut backlog = Vec::new();
+        let error = loop {
+            match connect_unix(&path, Duration::from_millis(10)) {
+                Ok(stream) if backlog.len() < 10000 => backlog.push(stream),
+                Ok(_) => panic!("the backlog never filled up"),
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(error, format!("can't connect to {path}: timed out"));
+        let probe = Probe::Unix(path.clone());
+        let events = Events::new();
+        let result = run_probe(
+            probe,
+            &Configuration::default(),
+            Duration::from_secs(1),
+            &events,
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result, Err(format!("can't connect to {path}: timed out")));
+    }
+}
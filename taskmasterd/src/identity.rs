@@ -0,0 +1,238 @@
+use crate::configuration::Configuration;
+use libc::{c_char, c_int, gid_t, uid_t};
+use std::ffi::{CStr, CString};
+use std::io;
+
+const LOOKUP_BUFFER_SIZE: usize = 16384;
+
+#[derive(Debug, Default, Clone)]
+pub struct Identity {
+    pub uid: Option<uid_t>,
+    pub gid: Option<gid_t>,
+    pub groups: Option<Vec<gid_t>>,
+}
+
+fn to_cstring(name: &str) -> Result<CString, String> {
+    CString::new(name).map_err(|_| format!("\"{name}\" contains a nul byte"))
+}
+
+pub fn lookup_user(name: &str) -> Result<(uid_t, gid_t), String> {
+    let c_name = to_cstring(name)?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as c_char; LOOKUP_BUFFER_SIZE];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let ret = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 {
+        return Err(format!(
+            "can't look up user \"{name}\": {}",
+            io::Error::from_raw_os_error(ret)
+        ));
+    }
+    if result.is_null() {
+        return Err(format!("unknown user \"{name}\""));
+    }
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+pub fn lookup_group(name: &str) -> Result<gid_t, String> {
+    let c_name = to_cstring(name)?;
+    let mut group: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as c_char; LOOKUP_BUFFER_SIZE];
+    let mut result: *mut libc::group = std::ptr::null_mut();
+    let ret = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 {
+        return Err(format!(
+            "can't look up group \"{name}\": {}",
+            io::Error::from_raw_os_error(ret)
+        ));
+    }
+    if result.is_null() {
+        return Err(format!("unknown group \"{name}\""));
+    }
+    Ok(group.gr_gid)
+}
+
+fn lookup_group_list(user: &str, gid: gid_t) -> Result<Vec<gid_t>, String> {
+    let c_name = to_cstring(user)?;
+    let mut groups: Vec<gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as c_int;
+        let ret =
+            unsafe { libc::getgrouplist(c_name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if ret != -1 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+        if count as usize <= groups.len() {
+            return Err(format!("can't get the group list of \"{user}\""));
+        }
+        groups.resize(count as usize, 0);
+    }
+}
+
+pub fn user_name(uid: uid_t) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as c_char; LOOKUP_BUFFER_SIZE];
+    let mut result: *mut libc::passwd = std::ptr::null_mut();
+    let ret = unsafe {
+        libc::getpwuid_r(
+            uid,
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if ret != 0 || result.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(passwd.pw_name) }
+        .to_str()
+        .ok()
+        .map(String::from)
+}
+
+pub fn user_groups(uid: uid_t, gid: gid_t) -> Vec<gid_t> {
+    match user_name(uid) {
+        Some(name) => lookup_group_list(&name, gid).unwrap_or_else(|_| vec![gid]),
+        None => vec![gid],
+    }
+}
+
+impl Identity {
+    pub fn resolve(configuration: &Configuration) -> Result<Identity, String> {
+        let mut identity = Identity::default();
+        let mut supplementary = Vec::new();
+        for name in &configuration.supplementary_groups {
+            supplementary
+                .push(lookup_group(name).map_err(|e| format!("supplementary_groups: {e}"))?);
+        }
+        if let Some(group) = &configuration.group {
+            identity.gid = Some(lookup_group(group).map_err(|e| format!("group: {e}"))?);
+        }
+        if let Some(user) = &configuration.user {
+            let (uid, primary_gid) = lookup_user(user).map_err(|e| format!("user: {e}"))?;
+            let gid = *identity.gid.get_or_insert(primary_gid);
+            let mut groups = lookup_group_list(user, gid).map_err(|e| format!("user: {e}"))?;
+            groups.extend(supplementary);
+            identity.uid = Some(uid);
+            identity.groups = Some(groups);
+        } else if !supplementary.is_empty() {
+            if let Some(gid) = identity.gid {
+                supplementary.insert(0, gid);
+            }
+            identity.groups = Some(supplementary);
+        }
+        Ok(identity)
+    }
+
+    pub fn apply(&self) -> io::Result<()> {
+        unsafe {
+            if let Some(groups) = &self.groups {
+                if libc::setgroups(groups.len(), groups.as_ptr()) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(gid) = self.gid {
+                if libc::setgid(gid) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(uid) = self.uid {
+                if libc::setuid(uid) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configuration(
+        user: Option<&str>,
+        group: Option<&str>,
+        supplementary: &[&str],
+    ) -> Configuration {
+        Configuration {
+            user: user.map(String::from),
+            group: group.map(String::from),
+            supplementary_groups: supplementary.iter().map(|name| name.to_string()).collect(),
+            ..Configuration::default()
+        }
+    }
+
+    #[test]
+    fn looks_up_names() {
+        assert_eq!(lookup_user("root"), Ok((0, 0)));
+        assert_eq!(lookup_group("root"), Ok(0));
+        assert_eq!(user_name(0).as_deref(), Some("root"));
+        assert_eq!(
+            lookup_user("no-such-user"),
+            Err("unknown user \"no-such-user\"".to_string())
+        );
+        assert_eq!(
+            lookup_group("no-such-group"),
+            Err("unknown group \"no-such-group\"".to_string())
+        );
+        assert!(lookup_user("ro\0ot").is_err());
+        assert_eq!(user_groups(4242, 4242), [4242]);
+    }
+
+    #[test]
+    fn resolves_identities() {
+        let (uid, primary_gid) = lookup_user("nobody").unwrap();
+        let tty = lookup_group("tty").unwrap();
+        let daemon = lookup_group("daemon").unwrap();
+
+        let identity = Identity::resolve(&configuration(None, None, &[])).unwrap();
+        assert_eq!(
+            (identity.uid, identity.gid, identity.groups),
+            (None, None, None)
+        );
+
+        let identity = Identity::resolve(&configuration(Some("nobody"), None, &[])).unwrap();
+        assert_eq!(identity.uid, Some(uid));
+        assert_eq!(identity.gid, Some(primary_gid));
+        assert!(identity.groups.unwrap().contains(&primary_gid));
+
+        let identity =
+            Identity::resolve(&configuration(Some("nobody"), Some("daemon"), &["tty"])).unwrap();
+        assert_eq!(identity.gid, Some(daemon));
+        let groups = identity.groups.unwrap();
+        assert!(
+            groups.contains(&daemon) && groups.ends_with(&[tty]),
+            "{groups:?}"
+        );
+
+        let identity = Identity::resolve(&configuration(None, Some("daemon"), &["tty"])).unwrap();
+        assert_eq!(identity.uid, None);
+        assert_eq!(identity.groups, Some(vec![daemon, tty]));
+
+        let error = Identity::resolve(&configuration(None, None, &["no-such-group"])).unwrap_err();
+        assert_eq!(
+            error,
+            "supplementary_groups: unknown group \"no-such-group\""
+        );
+        assert!(Identity::resolve(&configuration(Some("no-such-user"), None, &[])).is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+const ENV_PREFIX: &str = "ENV:";
+const PROGRAM_NAME: &str = "program_name";
+const PROCESS_NUM: &str = "process_num";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Command,
+    Value,
+}
+
+pub struct Context<'a> {
+    pub program_name: &'a str,
+    pub process_num: usize,
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+fn read_until(chars: &mut Peekable<Chars>, end: char, opening: &str) -> Result<String, String> {
+    let mut content = String::new();
+    loop {
+        match chars.next() {
+            Some(c) if c == end => return Ok(content),
+            Some(c) => content.push(c),
+            None => return Err(format!("unterminated {opening}{content}")),
+        }
+    }
+}
+
+pub fn lookup_variable(expression: &str) -> Result<String, String> {
+    let (name, default) = match expression.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (expression, None),
+    };
+    if !is_variable_name(name) {
+        return Err(format!("invalid variable name \"{name}\""));
+    }
+    match (std::env::var(name), default) {
+        (Ok(value), Some(default)) if value.is_empty() => Ok(default.to_string()),
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => Ok(default.to_string()),
+        (Err(_), None) => Ok(String::new()),
+    }
+}
+
+fn expand_variable(
+    chars: &mut Peekable<Chars>,
+    mode: Mode,
+    expanded: &mut String,
+) -> Result<(), String> {
+    if chars.peek() != Some(&'{') {
+        expanded.push('$');
+        return Ok(());
+    }
+    chars.next();
+    let expression = read_until(chars, '}', "${")?;
+    match expression.strip_prefix(ENV_PREFIX) {
+        Some(expression) => expanded.push_str(&lookup_variable(expression)?),
+        None if mode == Mode::Value => expanded.push_str(&lookup_variable(&expression)?),
+        None => {
+            expanded.push_str("${");
+            expanded.push_str(&expression);
+            expanded.push('}');
+        }
+    }
+    Ok(())
+}
+
+fn expand_template(
+    chars: &mut Peekable<Chars>,
+    context: &Context,
+    expanded: &mut String,
+) -> Result<(), String> {
+    if chars.peek() != Some(&'(') {
+        expanded.push('%');
+        return Ok(());
+    }
+    chars.next();
+    let name = read_until(chars, ')', "%(")?;
+    let mut spec = String::new();
+    while let Some(&c) = chars.peek() {
+        chars.next();
+        if c.is_ascii_alphabetic() {
+            spec.push(c);
+            break;
+        }
+        spec.push(c);
+    }
+    match (name.as_str(), spec.as_str()) {
+        (PROGRAM_NAME, "s") => expanded.push_str(context.program_name),
+        (PROCESS_NUM, "d") => expanded.push_str(&context.process_num.to_string()),
+        (PROCESS_NUM, spec) if spec.starts_with('0') && spec.ends_with('d') && spec.len() > 2 => {
+            let width: usize = spec[1..spec.len() - 1]
+                .parse()
+                .map_err(|_| format!("invalid format %({name}){spec}"))?;
+            expanded.push_str(&format!("{:0width$}", context.process_num));
+        }
+        (PROGRAM_NAME | PROCESS_NUM, spec) => {
+            return Err(format!("invalid format %({name}){spec}"))
+        }
+        _ => {
+            return Err(format!(
+                "unknown template %({name}), expected {PROGRAM_NAME} or {PROCESS_NUM}"
+            ))
+        }
+    }
+    Ok(())
+}
+
+pub fn expand(value: &str, context: &Context, mode: Mode) -> Result<String, String> {
+    let mut chars = value.chars().peekable();
+    let mut expanded = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => expand_variable(&mut chars, mode, &mut expanded)?,
+            '%' => expand_template(&mut chars, context, &mut expanded)?,
+            c => expanded.push(c),
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTEXT: Context = Context {
+        program_name: "web",
+        process_num: 7,
+    };
+
+    #[test]
+    fn expands_templates() {
+        assert_eq!(
+            expand(
+                "%(program_name)s-%(process_num)d-%(process_num)03d",
+                &CONTEXT,
+                Mode::Value
+            ),
+            Ok("web-7-007".to_string())
+        );
+        assert_eq!(
+            expand("100%", &CONTEXT, Mode::Value),
+            Ok("100%".to_string())
+        );
+        assert!(expand("%(process_num)s", &CONTEXT, Mode::Value).is_err());
+        assert!(expand("%(host)s", &CONTEXT, Mode::Value).is_err());
+        assert!(expand("%(program_name", &CONTEXT, Mode::Value).is_err());
+    }
+
+    #[test]
+    fn expands_environment_variables() {
+        std::env::set_var("TASKMASTER_TEST_INTERPOLATION", "value");
+        std::env::remove_var("TASKMASTER_TEST_UNSET");
+        assert_eq!(
+            expand(
+                "${TASKMASTER_TEST_INTERPOLATION}/$HOME",
+                &CONTEXT,
+                Mode::Value
+            ),
+            Ok("value/$HOME".to_string())
+        );
+        assert_eq!(
+            expand("${TASKMASTER_TEST_UNSET:-fallback}", &CONTEXT, Mode::Value),
+            Ok("fallback".to_string())
+        );
+        assert_eq!(
+            expand("${TASKMASTER_TEST_UNSET}", &CONTEXT, Mode::Value),
+            Ok(String::new())
+        );
+        assert!(expand("${1BAD}", &CONTEXT, Mode::Value).is_err());
+        assert!(expand("${TASKMASTER_TEST_INTERPOLATION", &CONTEXT, Mode::Value).is_err());
+    }
+
+    #[test]
+    fn leaves_shell_variables_to_commands() {
+        assert_eq!(
+            expand(
+                "echo ${HOME} ${ENV:TASKMASTER_TEST_UNSET:-x}",
+                &CONTEXT,
+                Mode::Command
+            ),
+            Ok("echo ${HOME} x".to_string())
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use crate::identity::user_name;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::{fchown, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+const GZIP_EXTENSION: &str = ".gz";
+const PERMISSION_BITS: u32 = 0o777;
+const TAIL_CHUNK_SIZE: u64 = 8192;
+
+pub struct LogFile {
+    path: String,
+    file: File,
+    size: u64,
+    max_bytes: u64,
+    backups: u32,
+    compress: bool,
+}
+
+fn segment_path(path: &str, number: u32, compress: bool) -> String {
+    if compress {
+        format!("{path}.{number}{GZIP_EXTENSION}")
+    } else {
+        format!("{path}.{number}")
+    }
+}
+
+fn existing_segment(path: &str, number: u32) -> Option<String> {
+    [false, true]
+        .into_iter()
+        .map(|compress| segment_path(path, number, compress))
+        .find(|segment| Path::new(segment).exists())
+}
+
+fn segments(path: &str) -> Vec<String> {
+    (1..)
+        .map_while(|number| existing_segment(path, number))
+        .collect()
+}
+
+fn create_new(path: &str, mode: u32) -> io::Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .create_new(true)
+        .mode(mode & PERMISSION_BITS)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)
+}
+
+fn compress_file(source: &str, destination: &str) -> io::Result<()> {
+    let mut input = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(source)?;
+    let metadata = input.metadata()?;
+    match fs::remove_file(destination) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let output = create_new(destination, metadata.mode())?;
+    fchown(&output, Some(metadata.uid()), Some(metadata.gid()))?;
+    output.set_permissions(Permissions::from_mode(metadata.mode() & PERMISSION_BITS))?;
+    let mut encoder = GzEncoder::new(output, Compression::default());
+    io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(source)
+}
+
+fn open_segment(segment: &str) -> io::Result<Box<dyn Read>> {
+    let file = File::open(segment)?;
+    if segment.ends_with(GZIP_EXTENSION) {
+        Ok(Box::new(MultiGzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+fn last_lines(reader: impl Read, num_lines: usize) -> io::Result<VecDeque<String>> {
+    let mut lines = VecDeque::new();
+    for line in BufReader::new(reader).split(b'\n') {
+        if lines.len() == num_lines {
+            lines.pop_front();
+        }
+        lines.push_back(String::from_utf8_lossy(&line?).into_owned());
+    }
+    Ok(lines)
+}
+
+fn read_last_lines(file: &mut File, num_lines: usize) -> io::Result<Vec<u8>> {
+    let end = file.seek(SeekFrom::End(0))?;
+    let mut position = end;
+    let mut tail = Vec::new();
+    let mut newlines = 0;
+    while position > 0 && num_lines > 0 {
+        let size = position.min(TAIL_CHUNK_SIZE);
+        position -= size;
+        let mut chunk = vec![0u8; size as usize];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut chunk)?;
+        for i in (0..chunk.len()).rev() {
+            if chunk[i] != b'\n' || position + i as u64 + 1 == end {
+                continue;
+            }
+            newlines += 1;
+            if newlines == num_lines {
+                chunk.drain(..=i);
+                chunk.extend(tail);
+                return Ok(chunk);
+            }
+        }
+        chunk.extend(tail);
+        tail = chunk;
+    }
+    Ok(tail)
+}
+
+fn segment_tail(segment: &str, num_lines: usize) -> io::Result<VecDeque<String>> {
+    if segment.ends_with(GZIP_EXTENSION) {
+        return last_lines(open_segment(segment)?, num_lines);
+    }
+    let tail = read_last_lines(&mut File::open(segment)?, num_lines)?;
+    last_lines(tail.as_slice(), num_lines)
+}
+
+pub fn read_tail(path: &str, num_lines: Option<usize>) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open file {path}: {e}"))?;
+    let Some(num_lines) = num_lines else {
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .map_err(|e| format!("Failed to read file {path}: {e}"))?;
+        return Ok(content);
+    };
+    let content = read_last_lines(&mut file, num_lines)
+        .map_err(|e| format!("Failed to read file {path}: {e}"))?;
+    let ends_with_newline = content.last().is_none_or(|byte| *byte == b'\n');
+    let mut lines = last_lines(content.as_slice(), num_lines).map_err(|e| e.to_string())?;
+    for segment in segments(path) {
+        if lines.len() >= num_lines {
+            break;
+        }
+        let older = segment_tail(&segment, num_lines - lines.len())
+            .map_err(|e| format!("Failed to read file {segment}: {e}"))?;
+        for line in older.into_iter().rev() {
+            lines.push_front(line);
+        }
+    }
+    let mut output = Vec::from(lines).join("\n");
+    if ends_with_newline && !output.is_empty() {
+        output += "\n";
+    }
+    Ok(output)
+}
+
+pub fn check_private_directory(directory: &Path) -> Result<(), String> {
+    let metadata =
+        fs::symlink_metadata(directory).map_err(|e| format!("{}: {e}", directory.display()))?;
+    let owner = unsafe { libc::geteuid() };
+    if !metadata.is_dir() {
+        return Err(format!("{}: not a directory", directory.display()));
+    }
+    if metadata.uid() != owner {
+        return Err(format!(
+            "{}: should be owned by {}",
+            directory.display(),
+            user_name(owner).unwrap_or(owner.to_string())
+        ));
+    }
+    if metadata.mode() & 0o022 != 0 {
+        return Err(format!(
+            "{}: should not be writable by its group or others",
+            directory.display()
+        ));
+    }
+    Ok(())
+}
+
+pub fn clear(path: &str) -> io::Result<()> {
+    OpenOptions::new().write(true).open(path)?.set_len(0)?;
+    for segment in segments(path) {
+        fs::remove_file(segment)?;
+    }
+    Ok(())
+}
+
+impl LogFile {
+    pub fn new(path: &str, file: File, max_bytes: u64, backups: u32, compress: bool) -> LogFile {
+        LogFile {
+            path: path.to_string(),
+            size: file.metadata().map(|metadata| metadata.len()).unwrap_or(0),
+            file,
+            max_bytes,
+            backups,
+            compress,
+        }
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        let metadata = self.file.metadata()?;
+        let file = match create_new(&self.path, metadata.mode()) {
+            Ok(file) => {
+                fchown(&file, Some(metadata.uid()), Some(metadata.gid()))?;
+                file.set_permissions(Permissions::from_mode(metadata.mode() & PERMISSION_BITS))?;
+                file
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => OpenOptions::new()
+                .append(true)
+                .custom_flags(libc::O_NOFOLLOW)
+                .open(&self.path)?,
+            Err(e) => return Err(e),
+        };
+        self.size = file.metadata()?.len();
+        self.file = file;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<String> {
+        if self.backups == 0 {
+            self.file.set_len(0)?;
+            self.size = 0;
+            return Ok(format!("{} was truncated", self.path));
+        }
+        if let Some(oldest) = existing_segment(&self.path, self.backups) {
+            fs::remove_file(oldest)?;
+        }
+        for number in (1..self.backups).rev() {
+            if let Some(segment) = existing_segment(&self.path, number) {
+                let compressed = segment.ends_with(GZIP_EXTENSION);
+                fs::rename(&segment, segment_path(&self.path, number + 1, compressed))?;
+            }
+        }
+        let first = segment_path(&self.path, 1, false);
+        fs::rename(&self.path, &first)?;
+        self.reopen()?;
+        if self.compress {
+            compress_file(&first, &segment_path(&self.path, 1, true))?;
+        }
+        Ok(format!("{} was rotated", self.path))
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<Option<String>, String> {
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            if metadata.len() < self.size {
+                self.size = metadata.len();
+            }
+        }
+        self.file
+            .write_all(data)
+            .map_err(|e| format!("Failed to write {}: {e}", self.path))?;
+        self.size += data.len() as u64;
+        if self.size < self.max_bytes {
+            return Ok(None);
+        }
+        self.rotate()
+            .map(Some)
+            .map_err(|e| format!("Failed to rotate {}: {e}", self.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path =
+                std::env::temp_dir().join(format!("taskmaster-log-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn path(&self, name: &str) -> String {
+            self.0.join(name).display().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn log_file(path: &str, max_bytes: u64, backups: u32, compress: bool) -> LogFile {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .unwrap();
+        LogFile::new(path, file, max_bytes, backups, compress)
+    }
+
+    fn tail(path: &str, num_lines: Option<usize>) -> String {
+        read_tail(path, num_lines).unwrap()
+    }
+
+    #[test]
+    fn rotates_and_drops_the_oldest_segment() {
+        let dir = TempDir::new("rotate");
+        let path = dir.path("out.log");
+        let mut log = log_file(&path, 6, 2, false);
+        for line in ["one\n", "two\n", "three\n", "four\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "four\n");
+        assert_eq!(fs::read_to_string(format!("{path}.1")).unwrap(), "three\n");
+        assert_eq!(
+            fs::read_to_string(format!("{path}.2")).unwrap(),
+            "one\ntwo\n"
+        );
+        log.write(b"five\n").unwrap();
+        assert_eq!(fs::read_to_string(format!("{path}.2")).unwrap(), "three\n");
+        assert!(!Path::new(&format!("{path}.3")).exists());
+    }
+
+    #[test]
+    fn truncates_without_backups() {
+        let dir = TempDir::new("truncate");
+        let path = dir.path("out.log");
+        let mut log = log_file(&path, 4, 0, false);
+        assert_eq!(
+            log.write(b"hello\n").unwrap(),
+            Some(format!("{path} was truncated"))
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        assert!(segments(&path).is_empty());
+    }
+
+    #[test]
+    fn tails_across_compressed_segments() {
+        let dir = TempDir::new("compress");
+        let path = dir.path("out.log");
+        let mut log = log_file(&path, 8, 3, true);
+        for line in ["first\n", "second\n", "third\n", "fourth\n", "last\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+        assert_eq!(
+            segments(&path),
+            [format!("{path}.1.gz"), format!("{path}.2.gz")]
+        );
+        assert_eq!(tail(&path, None), "last\n");
+        assert_eq!(tail(&path, Some(2)), "fourth\nlast\n");
+        assert_eq!(
+            tail(&path, Some(10)),
+            "first\nsecond\nthird\nfourth\nlast\n"
+        );
+        clear(&path).unwrap();
+        assert!(segments(&path).is_empty());
+    }
+
+    #[test]
+    fn tails_from_the_end_of_big_files() {
+        let dir = TempDir::new("seek");
+        let path = dir.path("out.log");
+        let content: String = (0..2000).map(|i| format!("line {i}\n")).collect();
+        fs::write(&path, &content).unwrap();
+        assert_eq!(tail(&path, Some(2)), "line 1998\nline 1999\n");
+        assert_eq!(tail(&path, Some(2000)), content);
+        assert_eq!(tail(&path, Some(5000)), content);
+        assert_eq!(tail(&path, Some(0)), "");
+        fs::write(&path, "first\n\nlast").unwrap();
+        assert_eq!(tail(&path, Some(1)), "last");
+        assert_eq!(tail(&path, Some(2)), "\nlast");
+        fs::write(format!("{path}.1"), &content).unwrap();
+        assert_eq!(tail(&path, Some(4)), "line 1999\nfirst\n\nlast");
+        assert_eq!(
+            read_tail(&dir.path("missing.log"), Some(1)).unwrap_err(),
+            format!(
+                "Failed to open file {}: No such file or directory (os error 2)",
+                dir.path("missing.log")
+            )
+        );
+    }
+
+    #[test]
+    fn rotation_does_not_follow_symlinks() {
+        let dir = TempDir::new("symlink");
+        let path = dir.path("out.log");
+        let victim = dir.path("victim");
+        fs::write(&victim, "secret").unwrap();
+        let mut log = log_file(&path, 4, 1, true);
+        symlink(&victim, format!("{path}.1.gz")).unwrap();
+        log.write(b"hello\n").unwrap();
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "secret");
+        assert!(!fs::symlink_metadata(format!("{path}.1.gz"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+    }
+
+    #[test]
+    fn refuses_shared_directories() {
+        let dir = TempDir::new("private");
+        fs::set_permissions(&dir.0, Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(check_private_directory(&dir.0), Ok(()));
+        fs::set_permissions(&dir.0, Permissions::from_mode(0o777)).unwrap();
+        assert!(check_private_directory(&dir.0).is_err());
+        let link = dir.path("link");
+        symlink(&dir.0, &link).unwrap();
+        assert!(check_private_directory(Path::new(&link)).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use crate::settings::{LogLevel, LogRetention};
 use std::collections::VecDeque;
 use std::fs::{File, OpenOptions};
 use std::io::Write;
@@ -18,7 +19,24 @@ pub struct Logger {
     pub history: VecDeque<LogLine>,
     file: File,
     idx: usize,
-    http_log_stream: Option<(u16, TcpStream)>,
+    level: LogLevel,
+    http_log_stream: Option<(String, TcpStream)>,
+}
+
+pub fn http_request(target: &str, body: &str) -> String {
+    let host = target.rsplit_once(':').map_or(target, |(host, _)| host);
+    format!(
+        "POST /e28d4bc5-666f-4b91-92a4-b46547c6a1cd HTTP/1.1\r\n\
+        Connection: Keep-Alive\r\n\
+        Host: {}\r\n\
+        Content-Type: application/x-www-form-urlencoded\r\n\
+        Content-Length: {}\r\n\
+        \r\n\
+        {}",
+        host,
+        body.len(),
+        body
+    )
 }
 
 impl Logger {
@@ -44,34 +62,44 @@ impl Logger {
             .collect()
     }
 
-    pub fn new(file_path: &'static str) -> Result<Self, String> {
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
+    pub fn new(file_path: &str, retention: LogRetention, level: LogLevel) -> Result<Self, String> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true);
+        match retention {
+            LogRetention::Remove | LogRetention::Truncate => options.truncate(true),
+            LogRetention::Append => options.append(true),
+        };
+        let file = options
             .open(file_path)
             .map_err(|e| format!("Can't create logging file: {file_path}. Error: {e}"))?;
         Ok(Logger {
             history: VecDeque::with_capacity(BUFFER_SIZE),
             file,
             idx: 0,
+            level,
             http_log_stream: None,
         })
     }
 
     pub fn enable_http_logging(&mut self, port: u16) -> Result<(), String> {
+        self.enable_http_logging_to(format!("localhost:{port}"))
+    }
+
+    pub fn enable_http_logging_to(&mut self, target: String) -> Result<(), String> {
         if let Some(_) = self.http_log_stream {
             return Err("http logging is already enabled".to_string());
         }
-        let stream = TcpStream::connect(format!("{}:{}", "localhost", port))
-            .map_err(|e| format!("can't connect to localhost:{port}: {e}"))?;
-        self.http_log(format!(
-            "Connection with localhost:{port} has been established"
-        ));
-        self.http_log_stream = Some((port, stream));
+        let stream =
+            TcpStream::connect(&target).map_err(|e| format!("can't connect to {target}: {e}"))?;
+        self.http_log(
+            LogLevel::Info,
+            format!("Connection with {target} has been established"),
+        );
+        self.http_log_stream = Some((target, stream));
         let history: Vec<String> = self.history.iter().map(|(_, msg)| msg.clone()).collect();
         for msg in history.iter() {
             if let Err(err_msg) = self.do_log_via_http(msg) {
-                return Err(self.http_log(err_msg));
+                return Err(self.http_log(LogLevel::Error, err_msg));
             }
         }
         Ok(())
@@ -82,46 +110,37 @@ impl Logger {
             return "http logging is already disabled".to_string();
         }
         self.http_log_stream = None;
-        self.http_log(format!("http logging has been disabled"))
+        self.http_log(LogLevel::Info, format!("http logging has been disabled"))
     }
 
     pub fn get_http_logging_status(&mut self) -> String {
-        self.http_log("Http logging status is requested".to_string());
-        let message = if let Some((port, stream)) = &mut self.http_log_stream {
+        self.http_log(
+            LogLevel::Debug,
+            "Http logging status is requested".to_string(),
+        );
+        let message = if let Some((target, stream)) = &mut self.http_log_stream {
             match stream.write(&[]) {
-                Ok(_) => Ok(format!("enabled localhost:{}", port)),
-                Err(err) => Err(format!(
-                    "connection with localhost:{} is dead: {}",
-                    port, err
-                )),
+                Ok(_) => Ok(format!("enabled {target}")),
+                Err(err) => Err(format!("connection with {target} is dead: {err}")),
             }
         } else {
             Ok("disabled".to_string())
         };
-        let final_msg = message.unwrap_or_else(|err_msg| {
-            self.http_log_stream = None;
-            err_msg
-        });
-        self.http_log(final_msg)
+        match message {
+            Ok(message) => self.http_log(LogLevel::Info, message),
+            Err(err_msg) => {
+                self.http_log_stream = None;
+                self.http_log(LogLevel::Error, err_msg)
+            }
+        }
     }
 
     fn do_log_via_http(&mut self, body: &str) -> Result<(), String> {
-        if let Some((port, stream)) = &mut self.http_log_stream {
-            let request = format!(
-                "POST /e28d4bc5-666f-4b91-92a4-b46547c6a1cd HTTP/1.1\r\n\
-                Connection: Keep-Alive
-                Host: {} \r\n\
-                Content-Type: application/x-www-form-urlencoded\r\n\
-                Content-Length: {} \r\n\
-                \r\n\
-                {}",
-                "localhost",
-                body.len(),
-                body
-            );
+        if let Some((target, stream)) = &mut self.http_log_stream {
+            let request = http_request(target, body);
 
             if let Err(err) = stream.write_all(request.as_bytes()) {
-                let err_msg = format!("can't write log in localhost:{port}: {err}, disabling...");
+                let err_msg = format!("can't write log in {target}: {err}, disabling...");
                 self.http_log_stream = None;
                 return Err(err_msg);
             }
@@ -131,13 +150,19 @@ impl Logger {
         }
     }
 
-    fn do_log(&mut self, prefix: &'static str, message: &str) {
+    fn do_log(&mut self, prefix: &'static str, level: LogLevel, message: &str) {
+        if level > self.level {
+            return;
+        }
         let log_msg = format!(
             "[{prefix}]: {}{:?}\n",
             Logger::get_timestamp(),
             message.trim()
         );
-        print!("{log_msg}");
+        match level {
+            LogLevel::Error => eprint!("{log_msg}"),
+            _ => print!("{log_msg}"),
+        }
         if let Err(e) = self.file.write_all(log_msg.as_bytes()) {
             eprintln!("Error! Can't write log {message} in log file: {e}")
         }
@@ -158,31 +183,47 @@ impl Logger {
         }
     }
 
-    pub fn sth_log(&mut self, message: String) -> String {
-        self.do_log(MONITOR_THREAD_PREFIX, &message);
+    pub fn sth_log(&mut self, level: LogLevel, message: String) -> String {
+        self.do_log(MONITOR_THREAD_PREFIX, level, &message);
         message
     }
 
-    pub fn monit_log(&mut self, message: String) -> String {
-        self.do_log(MONITOR_PREFIX, &message);
+    pub fn monit_log(&mut self, level: LogLevel, message: String) -> String {
+        self.do_log(MONITOR_PREFIX, level, &message);
         message
     }
 
-    pub fn log<S: AsRef<str>>(&mut self, message: S) {
-        self.do_log(GLOBAL_PREFIX, message.as_ref());
+    pub fn log<S: AsRef<str>>(&mut self, level: LogLevel, message: S) {
+        self.do_log(GLOBAL_PREFIX, level, message.as_ref());
     }
 
-    pub fn resp_log(&mut self, message: String) -> String {
-        self.do_log(RESPONDER_PREFIX, message.as_ref());
+    pub fn resp_log(&mut self, level: LogLevel, message: String) -> String {
+        self.do_log(RESPONDER_PREFIX, level, message.as_ref());
         message
     }
 
-    pub fn http_log(&mut self, message: String) -> String {
-        self.do_log(HTTP_LOGGER_PREFIX, &message);
+    pub fn http_log(&mut self, level: LogLevel, message: String) -> String {
+        self.do_log(HTTP_LOGGER_PREFIX, level, &message);
         message
     }
+}
 
-    pub fn log_err<S: AsRef<str>>(&self, message: S) {
-        eprintln!("{}", message.as_ref())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_messages_by_level() {
+        let path = std::env::temp_dir().join(format!("taskmaster-logger-{}", std::process::id()));
+        let path = path.display().to_string();
+        let mut logger = Logger::new(&path, LogRetention::Truncate, LogLevel::Error).unwrap();
+        logger.sth_log(LogLevel::Info, "web[0]: is running now".to_string());
+        logger.resp_log(LogLevel::Debug, "Received via socket".to_string());
+        logger.sth_log(LogLevel::Error, "web[0]: Command: not found".to_string());
+        let content = std::fs::read_to_string(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(content.lines().count(), 1, "{content}");
+        assert!(content.contains("web[0]: Command: not found"));
+        assert_eq!(logger.get_history(None).len(), 1);
     }
 }
@@ -1,8 +1,23 @@
+mod access;
 mod action;
+mod cgroup;
+mod command_line;
 mod configuration;
+mod diagnostic;
+mod events;
+mod exit;
+mod health;
+mod identity;
+mod interpolation;
+mod log_file;
 mod logger;
 mod monitor;
+mod output;
+mod protocol;
+mod report;
 mod responder;
+mod rlimit;
+mod settings;
 mod sighup_handler;
 mod task;
 mod utils;
@@ -11,16 +26,29 @@ use configuration::Configuration;
 use daemonize::Daemonize;
 use logger::Logger;
 use monitor::Monitor;
-use responder::Responder;
+use responder::{Limits, Responder};
+use settings::{installed_settings, settings, LogLevel, LogRetention, Settings};
 use std::env;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-pub const UNIX_DOMAIN_SOCKET_PATH: &'static str = "/tmp/taskmaster.sock";
-pub const PID_FILE_PATH: &'static str = "/tmp/taskmasterd.pid";
-pub const LOG_FILE_PATH: &'static str = "/tmp/taskmasterd.log";
+const DEFAULT_TASK_USER: &str = "nobody";
+const DEFAULT_TASK_GROUP: &str = "daemon";
+const DEFAULT_MAX_CONNECTIONS: usize = 16;
+const DEFAULT_REQUEST_TIMEOUT: u64 = 30;
 
 const HELP_MESSAGE: &str = "Options are:\n\t--help: Show help info\
     \n\t--debug: Disables daemon mode\
+    \n\t--check-config: Validates the configuration file and exits\
+    \n\t--max-connections <n>: Maximum number of simultaneous clients (default 16)\
+    \n\t--request-timeout <seconds>: Time a client has to send a request or read an answer (default 30)\
+    \n\t--socket <path>: Control socket path (default /tmp/taskmaster.sock)\
+    \n\t--socket-owner <user>: Owner of the control socket\
+    \n\t--socket-group <group>: Group of the control socket\
+    \n\t--socket-mode <octal>: Permissions of the control socket (default 666)\
+    \n\t--pidfile <path>: Pid file path in daemon mode (default /tmp/taskmasterd.pid)\
+    \n\t--logfile <path>: Daemon log file path (default /tmp/taskmasterd.log)\
+    \n\t--log-retention <remove|truncate|append>: What happens to the previous log file (default remove)\
     \n\t<path_to_config_file>: Starts server with a configuration";
 
 macro_rules! error_exit {
@@ -33,9 +61,13 @@ macro_rules! error_exit {
 }
 
 fn remove_files() {
-    let _ = std::fs::remove_file(UNIX_DOMAIN_SOCKET_PATH);
-    let _ = std::fs::remove_file(PID_FILE_PATH);
-    let _ = std::fs::remove_file(LOG_FILE_PATH);
+    if let Some(settings) = installed_settings() {
+        let _ = std::fs::remove_file(&settings.socket);
+        let _ = std::fs::remove_file(&settings.pidfile);
+        if settings.log_retention == LogRetention::Remove {
+            let _ = std::fs::remove_file(&settings.logfile);
+        }
+    }
 }
 
 pub fn remove_and_exit(exit_code: i32) -> ! {
@@ -53,16 +85,57 @@ fn check_root_user() {
     }
 }
 
-fn parse_arguments() -> (bool, String) {
+struct Arguments {
+    should_daemonize: bool,
+    check_config: bool,
+    config_path: String,
+    limits: Limits,
+    settings: Vec<(String, String)>,
+}
+
+fn parse_option_value<T: std::str::FromStr>(option: &str, value: Option<String>) -> T {
+    match value {
+        None => error_exit!(2, "Error: {option} requires a value"),
+        Some(value) => match value.parse::<T>() {
+            Ok(value) => value,
+            Err(_) => error_exit!(2, "Error: Invalid value for {option}: \"{value}\""),
+        },
+    }
+}
+
+fn parse_arguments() -> Arguments {
     let mut should_daemonize = true;
+    let mut check_config = false;
     let mut filename: Option<String> = None;
-    for arg in env::args().skip(1) {
+    let mut max_connections = DEFAULT_MAX_CONNECTIONS;
+    let mut request_timeout = DEFAULT_REQUEST_TIMEOUT;
+    let mut settings = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
         match arg.as_str() {
             "--help" => {
                 println!("{}", HELP_MESSAGE);
                 remove_and_exit(0);
             }
             "--debug" => should_daemonize = false,
+            "--check-config" => check_config = true,
+            "--max-connections" => {
+                max_connections = parse_option_value(&arg, args.next());
+                if max_connections == 0 {
+                    error_exit!(2, "Error: --max-connections should be at least 1");
+                }
+            }
+            "--request-timeout" => {
+                request_timeout = parse_option_value(&arg, args.next());
+                if request_timeout == 0 {
+                    error_exit!(2, "Error: --request-timeout should be at least 1");
+                }
+            }
+            "--socket" | "--socket-owner" | "--socket-group" | "--socket-mode" | "--pidfile"
+            | "--logfile" | "--log-retention" => match args.next() {
+                Some(value) => settings.push((arg, value)),
+                None => error_exit!(2, "Error: {arg} requires a value"),
+            },
             _ => {
                 if arg.starts_with("-") {
                     error_exit!(2, "Error: Unknown option: {arg}");
@@ -79,30 +152,116 @@ fn parse_arguments() -> (bool, String) {
         }
     }
     match filename {
-        Some(filename) => (should_daemonize, filename),
+        Some(config_path) => Arguments {
+            should_daemonize,
+            check_config,
+            config_path,
+            limits: Limits {
+                max_connections,
+                request_timeout: Duration::from_secs(request_timeout),
+            },
+            settings,
+        },
         None => error_exit!(2, "Error: No configuration file given"),
     }
 }
 
-fn run_program(monitor: Monitor, logger: Arc<Mutex<Logger>>) {
+fn load_settings(config_path: &str, flags: Vec<(String, String)>) -> Result<Settings, String> {
+    let mut settings = Settings::from_yml(config_path)?;
+    for (flag, value) in flags {
+        match flag.as_str() {
+            "--socket" => settings.socket = value,
+            "--socket-owner" => settings.socket_owner = Some(value),
+            "--socket-group" => settings.socket_group = Some(value),
+            "--socket-mode" => settings.socket_mode = settings::parse_mode(&value)?,
+            "--pidfile" => settings.pidfile = value,
+            "--logfile" => settings.logfile = value,
+            "--log-retention" => settings.log_retention = settings::parse_log_retention(&value)?,
+            _ => return Err(format!("Error: Unknown option: {flag}")),
+        }
+    }
+    settings.validate()?;
+    Ok(settings)
+}
+
+fn run_program(monitor: Monitor, logger: Arc<Mutex<Logger>>, limits: Limits) {
     monitor.track();
-    Responder::listen(monitor, logger);
+    Responder::listen(monitor, logger, limits);
 }
 
 fn main() {
+    let Arguments {
+        should_daemonize,
+        check_config,
+        config_path,
+        limits,
+        settings: settings_flags,
+    } = parse_arguments();
+    match load_settings(&config_path, settings_flags) {
+        Ok(settings) => settings.install(),
+        Err(err_msg) => {
+            eprintln!("{err_msg}");
+            std::process::exit(2);
+        }
+    }
+    if check_config {
+        match Configuration::check_yml(&config_path) {
+            Ok(conf) => {
+                println!(
+                    "{config_path}: configuration is valid ({} programs)",
+                    conf.tasks.len()
+                );
+                std::process::exit(0);
+            }
+            Err(err_msg) => {
+                eprintln!("{err_msg}");
+                std::process::exit(2);
+            }
+        }
+    }
     remove_files();
-    let (should_daemonize, config_path) = parse_arguments();
     sighup_handler::set_sighup_handler();
 
-    match Logger::new(LOG_FILE_PATH) {
-        Ok(logger) => {
+    match Logger::new(
+        &settings().logfile,
+        settings().log_retention,
+        settings().log_level,
+    ) {
+        Ok(mut logger) => {
+            if let Some(target) = &settings().http_logging {
+                if let Err(err_msg) = logger.enable_http_logging_to(target.clone()) {
+                    logger.log(LogLevel::Error, format!("http_logging: {err_msg}"));
+                }
+            }
             let logger = Arc::new(Mutex::new(logger));
             println!("taskmasterd launched (PID {})", std::process::id());
 
             let mut monitor = Monitor::new(config_path.clone(), logger.clone());
+            match (&settings().user, should_daemonize) {
+                (Some(user), _) => {
+                    monitor.set_default_identity(Some(user.clone()), settings().group.clone())
+                }
+                (None, true) => monitor.set_default_identity(
+                    Some(DEFAULT_TASK_USER.to_string()),
+                    Some(
+                        settings()
+                            .group
+                            .as_deref()
+                            .unwrap_or(DEFAULT_TASK_GROUP)
+                            .to_string(),
+                    ),
+                ),
+                (None, false) => {
+                    if settings().group.is_some() {
+                        monitor.set_default_identity(None, settings().group.clone());
+                    }
+                }
+            }
             match Configuration::from_yml(config_path, logger.clone()) {
                 Ok(conf) => {
-                    monitor.update_configuration(conf);
+                    monitor.set_access(conf.access);
+                    monitor.set_sources(conf.sources);
+                    monitor.update_configuration(conf.tasks);
                 }
                 Err(err_msg) => {
                     error_exit!(2, "{err_msg}");
@@ -112,19 +271,16 @@ fn main() {
             if should_daemonize {
                 check_root_user();
                 match Daemonize::new()
-                    .pid_file(PID_FILE_PATH)
-                    .chown_pid_file(true)
+                    .pid_file(&settings().pidfile)
                     .working_directory(".")
-                    .user("nobody")
-                    .group("daemon")
-                    .umask(0o022)
+                    .umask(settings().umask.unwrap_or(0o022))
                     .start()
                 {
-                    Ok(_) => run_program(monitor, logger),
+                    Ok(_) => run_program(monitor, logger, limits),
                     Err(e) => eprintln!("Can't daemonize: {e}. Already launched or check sudo"),
                 }
             } else {
-                run_program(monitor, logger);
+                run_program(monitor, logger, limits);
             }
         }
         Err(error) => {
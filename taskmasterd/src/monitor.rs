@@ -1,21 +1,40 @@
+use crate::access::{Access, Peer};
 use crate::action::{Action, OutputType, TailType};
-use crate::configuration::State::{BACKOFF, EXITED, FATAL, RUNNING, STARTING, STOPPED, STOPPING};
-use crate::configuration::{AutoRestart, Configuration};
+use crate::cgroup::Cgroup;
+use crate::configuration::State::{
+    BACKOFF, EXITED, FATAL, RUNNING, STARTING, STOPPED, STOPPING, UNHEALTHY,
+};
+use crate::configuration::{start_order, AutoRestart, Configuration};
+use crate::events::Events;
 use crate::logger::Logger;
 use crate::remove_and_exit;
+use crate::report::{
+    Change, ConfigurationChange, ErrorKind, GroupStatus, ProcessConfiguration, ProcessResult,
+    ProcessStatus, Report,
+};
 use crate::responder::Respond;
+use crate::settings::{settings, LogLevel};
 use crate::task::Task;
-use crate::utils::is_time_elapsed;
-use std::collections::BTreeMap;
+use crate::utils::{earliest, is_time_elapsed};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
 use std::thread;
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 
 pub struct Monitor {
     tasks: Arc<Mutex<BTreeMap<String, Vec<Task>>>>,
     deprecated_tasks: Arc<Mutex<Vec<Task>>>,
     logger: Arc<Mutex<Logger>>,
     config_path: String,
+    default_identity: Option<(Option<String>, Option<String>)>,
+    cgroup_root: PathBuf,
+    events: Arc<Events>,
+    access: Access,
+    sources: BTreeMap<String, String>,
+    is_shutting_down: Arc<AtomicBool>,
 }
 
 impl Monitor {
@@ -25,631 +44,1356 @@ impl Monitor {
             deprecated_tasks: Arc::new(Mutex::new(Vec::new())),
             logger,
             config_path,
+            default_identity: None,
+            cgroup_root: PathBuf::from(&settings().cgroup_root),
+            events: Arc::new(Events::new()),
+            access: Access::default(),
+            sources: BTreeMap::new(),
+            is_shutting_down: Arc::new(AtomicBool::new(false)),
         }
     }
 
-    pub fn update_configuration(&mut self, configs: BTreeMap<String, Configuration>) -> String {
+    pub fn set_default_identity(&mut self, user: Option<String>, group: Option<String>) {
+        self.default_identity = Some((user, group));
+    }
+
+    fn apply_default_identity(&self, mut config: Configuration) -> Configuration {
+        if let Some((user, group)) = &self.default_identity {
+            if config.user.is_none() {
+                config.user = user.clone();
+                if config.group.is_none() {
+                    config.group = group.clone();
+                }
+            }
+        }
+        config
+    }
+
+    fn create_task_group(
+        &self,
+        task_name: &str,
+        config: &Configuration,
+        logger: &mut MutexGuard<Logger>,
+    ) -> Vec<Task> {
+        let cgroup = Cgroup::for_task(&self.cgroup_root, task_name, config).unwrap_or_else(|err| {
+            logger.monit_log(
+                LogLevel::Error,
+                format!("{task_name}: cgroup limits are ignored, {err}"),
+            );
+            None
+        });
+        (0..config.num_procs as usize)
+            .map(|index| {
+                let mut task = Task::new(config, task_name, index, &self.events);
+                task.cgroup = cgroup.clone();
+                task
+            })
+            .collect()
+    }
+
+    pub fn update_configuration(
+        &mut self,
+        configs: BTreeMap<String, Configuration>,
+    ) -> Vec<ConfigurationChange> {
+        let configs: BTreeMap<String, Configuration> = configs
+            .into_iter()
+            .map(|(name, config)| (name, self.apply_default_identity(config)))
+            .collect();
         let mut tasks = self.tasks.lock().unwrap();
         let mut logger = self.logger.lock().unwrap();
-        let mut result = String::new();
-        logger.monit_log("Configuration loading has been initiated".to_string());
+        let mut changes = Vec::new();
+        logger.monit_log(
+            LogLevel::Info,
+            "Configuration loading has been initiated".to_string(),
+        );
         for (task_name, config) in &configs {
-            let update = Task::new(&config);
             match tasks.remove(task_name) {
                 None => {
-                    logger.monit_log(format!("New task: {task_name} has been added"));
+                    logger.monit_log(
+                        LogLevel::Info,
+                        format!("New task: {task_name} has been added"),
+                    );
                     tasks.insert(
                         task_name.clone(),
-                        (0..config.num_procs).map(|_| Task::new(&config)).collect(),
+                        self.create_task_group(task_name, config, &mut logger),
                     );
-                    result += &format!("{task_name}: added\n");
+                    changes.push(ConfigurationChange {
+                        name: task_name.clone(),
+                        change: Change::Added,
+                    });
                 }
                 Some(old) => {
-                    if old[0].configuration != update.configuration {
+                    if old[0].definition != *config {
                         tasks.insert(
                             task_name.clone(),
-                            (0..config.num_procs).map(|_| Task::new(&config)).collect(),
+                            self.create_task_group(task_name, config, &mut logger),
+                        );
+                        logger.monit_log(
+                            LogLevel::Info,
+                            format!(
+                                "Existing task: {task_name} was modified, changes has been applied"
+                            ),
                         );
-                        logger.monit_log(format!(
-                            "Existing task: {task_name} was modified, changes has been applied"
-                        ));
                         self.deprecated_tasks.lock().unwrap().extend(old);
-                        result += &format!("{task_name}: updated\n")
+                        changes.push(ConfigurationChange {
+                            name: task_name.clone(),
+                            change: Change::Updated,
+                        });
                     } else {
                         tasks.insert(task_name.clone(), old);
-                        logger.monit_log(format!("Existing task: {task_name} wasn't modified"));
+                        logger.monit_log(
+                            LogLevel::Info,
+                            format!("Existing task: {task_name} wasn't modified"),
+                        );
                     }
                 }
             }
         }
-        tasks.retain(|task_name, _task| {
-            let is_present = configs.contains_key(task_name);
-            if !is_present {
-                result += &logger.monit_log(format!("{task_name} has been deleted\n"));
+        let removed: Vec<String> = tasks
+            .keys()
+            .filter(|task_name| !configs.contains_key(*task_name))
+            .cloned()
+            .collect();
+        for task_name in removed {
+            if let Some(old) = tasks.remove(&task_name) {
+                self.deprecated_tasks.lock().unwrap().extend(old);
             }
-            is_present
-        });
-        if result.is_empty() {
-            "Already up to date.".to_string()
-        } else {
-            result
+            logger.monit_log(LogLevel::Info, format!("{task_name} has been deleted"));
+            changes.push(ConfigurationChange {
+                name: task_name,
+                change: Change::Removed,
+            });
         }
+        self.events.wake();
+        changes
     }
 
-    fn clear_logs(&mut self, task_name: &str) -> String {
+    fn task_order(tasks: &BTreeMap<String, Vec<Task>>) -> Vec<String> {
+        start_order(tasks.iter().filter_map(|(name, task_group)| {
+            task_group.first().map(|task| (name, &task.definition))
+        }))
+        .unwrap_or_else(|_| tasks.keys().cloned().collect())
+    }
+
+    fn running_dependents(tasks: &BTreeMap<String, Vec<Task>>, name: &str) -> Vec<String> {
+        tasks
+            .iter()
+            .filter(|(_, task_group)| {
+                task_group.first().is_some_and(|task| {
+                    task.definition
+                        .depends_on
+                        .iter()
+                        .any(|dependency| dependency == name)
+                }) && task_group.iter().any(|process| process.child.is_some())
+            })
+            .map(|(dependent, _)| dependent.clone())
+            .collect()
+    }
+
+    fn missing_dependencies(tasks: &BTreeMap<String, Vec<Task>>, name: &str) -> Vec<String> {
+        let Some(task) = tasks.get(name).and_then(|task_group| task_group.first()) else {
+            return Vec::new();
+        };
+        task.definition
+            .depends_on
+            .iter()
+            .filter(|dependency| {
+                !tasks.get(*dependency).is_some_and(|task_group| {
+                    task_group
+                        .iter()
+                        .all(|process| matches!(process.state, RUNNING(_)))
+                })
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn process_label(task_name: &str, task_group: &[Task], index: usize) -> String {
+        match task_group.len() {
+            1 => task_name.to_string(),
+            _ => format!("{task_name}[{index}]"),
+        }
+    }
+
+    fn clear_logs(&mut self, task_name: &str, index: Option<usize>) -> Report {
         let tasks = self.tasks.lock().unwrap();
         let mut logger = self.logger.lock().unwrap();
-        logger.monit_log(match tasks.get(task_name) {
-            None => format!("Failed to clear the logs of {task_name}: task does not exist"),
-            Some(task_group) => task_group[0].clear_logs(task_name),
-        })
+        let Some(task_group) = tasks.get(task_name) else {
+            return Report::error(
+                ErrorKind::TaskNotFound,
+                logger.monit_log(
+                    LogLevel::Info,
+                    format!("Failed to clear the logs of {task_name}: task does not exist"),
+                ),
+            );
+        };
+        match index {
+            Some(index) => match task_group.get(index) {
+                None => Report::error(
+                    ErrorKind::ProcessNotFound,
+                    logger.monit_log(
+                        LogLevel::Info,
+                        format!(
+                        "Failed to clear the logs of {task_name}: process {index} does not exist"
+                    ),
+                    ),
+                ),
+                Some(task) => Report::info(logger.monit_log(
+                    LogLevel::Info,
+                    task.clear_logs(&Self::process_label(task_name, task_group, index)),
+                )),
+            },
+            None => Report::info(
+                logger.monit_log(
+                    LogLevel::Info,
+                    task_group
+                        .iter()
+                        .enumerate()
+                        .map(|(index, task)| {
+                            task.clear_logs(&Self::process_label(task_name, task_group, index))
+                        })
+                        .collect::<String>(),
+                ),
+            ),
+        }
     }
 
-    fn get_task_status(&mut self, task_name: Option<String>) -> String {
+    fn get_group_status(name: &str, task_group: &[Task], shows_exits: bool) -> GroupStatus {
+        GroupStatus {
+            name: name.to_string(),
+            usage: task_group
+                .first()
+                .and_then(|task| task.cgroup.as_ref())
+                .and_then(|cgroup| cgroup.usage()),
+            processes: task_group
+                .iter()
+                .enumerate()
+                .map(|(index, task)| ProcessStatus::new(index, task))
+                .collect(),
+            shows_exits,
+        }
+    }
+
+    fn get_task_status(&mut self, task_name: Option<String>) -> Report {
         let tasks = self.tasks.lock().unwrap();
         let mut logger = self.logger.lock().unwrap();
-        return match task_name {
+        match task_name {
             None => {
                 logger.monit_log(
+                    LogLevel::Info,
                     "Task status: no task name was specified. Returning all tasks status"
                         .to_string(),
                 );
-                if tasks.is_empty() {
-                    return "No task found.".to_string();
-                }
-                tasks
-                    .iter()
-                    .map(|(name, task)| {
-                        let process_lines: Vec<String> = task
-                            .iter()
-                            .enumerate()
-                            .map(|(position, process)| format!("\n\t{position}. {process}"))
-                            .collect();
-                        if task.len() == 1 {
-                            format!("{name}: {}", task[0].to_string())
-                        } else {
-                            format!("{name}:\t\t{}", process_lines.join(""))
-                        }
-                    })
-                    .collect::<Vec<String>>()
-                    .join("\n")
+                Report::Status {
+                    groups: tasks
+                        .iter()
+                        .map(|(name, task)| Self::get_group_status(name, task, false))
+                        .collect(),
+                }
             }
             Some(ref task_name) => match tasks.get(task_name.as_str()) {
                 None => {
-                    logger.monit_log(format!("Task status: {task_name} wasn't found"));
-                    format!("Can't find \"{task_name}\" task")
+                    logger.monit_log(
+                        LogLevel::Info,
+                        format!("Task status: {task_name} wasn't found"),
+                    );
+                    Report::task_not_found(task_name)
                 }
                 Some(task) => {
-                    logger.monit_log(format!("Task status: {task_name} returning status"));
-                    format!(
-                        "{task_name}: {}",
-                        task.iter()
-                            .enumerate()
-                            .map(|(position, process)| format!("\n\t{position}. {process}"))
-                            .collect::<Vec<String>>()
-                            .join("")
-                    )
+                    logger.monit_log(
+                        LogLevel::Info,
+                        format!("Task status: {task_name} returning status"),
+                    );
+                    Report::Status {
+                        groups: vec![Self::get_group_status(task_name, task, true)],
+                    }
                 }
             },
-        };
+        }
     }
 
-    fn get_task_json_config_by_name(&mut self, name: &String) -> Option<String> {
+    fn get_task_config(&mut self, name: &String) -> Report {
         let tasks = self.tasks.lock().unwrap();
         let mut logger = self.logger.lock().unwrap();
-        return match tasks
-            .get(name.as_str())
-            .map(|task| task[0].get_json_configuration())
-        {
+        match tasks.get(name.as_str()) {
             None => {
-                logger.monit_log(format!("Get config: {name} wasn't found"));
-                None
+                logger.monit_log(LogLevel::Info, format!("Get config: {name} wasn't found"));
+                Report::task_not_found(name)
             }
-            Some(config) => {
-                logger.monit_log(format!(
-                    "Get config: returning {name} configuration in json format"
-                ));
-                Some(config)
+            Some(task) => {
+                logger.monit_log(
+                    LogLevel::Info,
+                    format!("Get config: returning {name} configuration in json format"),
+                );
+                Report::Configuration {
+                    name: name.clone(),
+                    source: self.sources.get(name).cloned(),
+                    configuration: Box::new(task[0].definition.clone()),
+                    processes: task
+                        .iter()
+                        .enumerate()
+                        .map(|(index, process)| {
+                            ProcessConfiguration::new(index, &process.configuration)
+                        })
+                        .collect(),
+                }
             }
-        };
+        }
+    }
+
+    fn log_result(logger: &mut MutexGuard<Logger>, result: ProcessResult) -> ProcessResult {
+        logger.monit_log(LogLevel::Info, result.to_string());
+        result
     }
 
-    fn restart_task(&mut self, name: &String, num: &Option<usize>) -> String {
+    fn restart_task(
+        &mut self,
+        name: &String,
+        num: &Option<usize>,
+    ) -> Result<Vec<ProcessResult>, Report> {
         let mut tasks = self.tasks.lock().unwrap();
         let mut logger = self.logger.lock().unwrap();
-        let mut result = String::new();
-        if let Some(task_group) = tasks.get_mut(name) {
-            match num {
-                None => {
-                    logger.monit_log(format!("All task in {name} will be restarted"));
-                    for (i, process) in task_group.iter_mut().enumerate() {
-                        if let RUNNING(_) = process.state {
-                            if let Err(e_msg) = process.stop() {
-                                result += &logger.monit_log(format!(
-                                    "{name}[{i}]: Error during the restart: {e_msg}\n"
-                                ));
-                            } else {
-                                process.restarts_left = process.configuration.start_retries;
-                                process.is_manual_restarting = true;
-                                result += &logger.monit_log(format!("{name}[{i}]: Restarting...\n"))
-                            }
+        let mut results = Vec::new();
+        let task_group = tasks
+            .get_mut(name)
+            .ok_or_else(|| Report::task_not_found(name))?;
+        match num {
+            None => {
+                logger.monit_log(
+                    LogLevel::Info,
+                    format!("All task in {name} will be restarted"),
+                );
+                for (i, process) in task_group.iter_mut().enumerate() {
+                    let result = if let RUNNING(_) | UNHEALTHY(_) = process.state {
+                        if let Err(e_msg) = process.stop() {
+                            ProcessResult::error(
+                                name,
+                                i,
+                                ErrorKind::StopFailed,
+                                format!("Error during the restart: {e_msg}"),
+                            )
                         } else {
-                            result += &logger.monit_log(format!(
-                                "{name}[{i}]: Can't be restarted. Current status {}. Required status: \"Running\"\n",
-                                process.state
-                            ))
+                            process.restarts_left = process.configuration.start_retries;
+                            process.restart_times.clear();
+                            process.is_manual_restarting = true;
+                            ProcessResult::ok(name, i, "Restarting...".to_string())
                         }
-                    }
+                    } else {
+                        ProcessResult::error(
+                            name,
+                            i,
+                            ErrorKind::InvalidState,
+                            format!(
+                                "Can't be restarted. Current status {}. Required status: \"Running\"",
+                                process.state
+                            ),
+                        )
+                    };
+                    results.push(Self::log_result(&mut logger, result));
                 }
-                Some(index) => match task_group.get_mut(*index) {
-                    None => {
-                        result += &logger.monit_log(format!(
-                            "{name}[{index}]: Can't be restarted, it doesn't exist\n"
-                        ))
-                    }
+            }
+            Some(index) => {
+                let result = match task_group.get_mut(*index) {
+                    None => ProcessResult::error(
+                        name,
+                        *index,
+                        ErrorKind::ProcessNotFound,
+                        "Can't be restarted, it doesn't exist".to_string(),
+                    ),
                     Some(task) => match task.stop() {
                         Ok(_) => {
                             task.is_manual_restarting = true;
-                            result += &logger.monit_log(format!("{name}[{index}]: Restarting...\n"))
-                        }
-                        Err(err) => {
-                            result += &logger
-                                .monit_log(format!("{name}[{index}]: Can't be restarted: {err}\n"))
+                            ProcessResult::ok(name, *index, "Restarting...".to_string())
                         }
+                        Err(err) => ProcessResult::error(
+                            name,
+                            *index,
+                            ErrorKind::StopFailed,
+                            format!("Can't be restarted: {err}"),
+                        ),
                     },
-                },
+                };
+                results.push(Self::log_result(&mut logger, result));
             }
-        } else {
-            result = format!("Can't find \"{name}\" task")
         }
-        result
+        Ok(results)
+    }
+
+    fn stop_process(process: &mut Task, name: &str, i: usize) -> ProcessResult {
+        match process.state {
+            _ if !process.waiting_for.is_empty() => {
+                process.cancel_waiting();
+                ProcessResult::ok(name, i, "No longer waiting".to_string())
+            }
+            BACKOFF(_) => {
+                process.state = STOPPED(Some(SystemTime::now()));
+                ProcessResult::ok(name, i, "No longer retrying".to_string())
+            }
+            RUNNING(_) | STARTING(_) | UNHEALTHY(_) => {
+                if let Err(e_msg) = process.stop() {
+                    ProcessResult::error(
+                        name,
+                        i,
+                        ErrorKind::StopFailed,
+                        format!("Error during the stop: {e_msg}"),
+                    )
+                } else {
+                    ProcessResult::ok(name, i, "Stopping...".to_string())
+                }
+            }
+            _ => ProcessResult::error(
+                name,
+                i,
+                ErrorKind::InvalidState,
+                format!("Can't be stopped. Current status {}", process.state),
+            ),
+        }
     }
 
-    fn stop_task(&mut self, name: &String, num: &Option<usize>) -> String {
+    fn stop_task_after_dependents(&mut self, name: &String) -> Result<Vec<ProcessResult>, Report> {
         let mut tasks = self.tasks.lock().unwrap();
+        let running_dependents = Self::running_dependents(&tasks, name);
+        if running_dependents.is_empty() {
+            drop(tasks);
+            return self.stop_task(name, &None);
+        }
         let mut logger = self.logger.lock().unwrap();
-        let mut result = String::new();
-        if let Some(task_group) = tasks.get_mut(name) {
-            match num {
-                None => {
-                    logger.monit_log(format!("All task in {name} will be stopped"));
-                    for (i, process) in task_group.iter_mut().enumerate() {
-                        result += &logger.monit_log(match process.state {
-                            RUNNING(_) | STARTING(_) => {
-                                if let Err(e_msg) = process.stop() {
-                                    format!("{name}[{i}]: Error during the stop: {e_msg}\n")
-                                } else {
-                                    format!("{name}[{i}]: Stopping...\n")
-                                }
-                            }
-                            _ => {
-                                format!(
-                                    "{name}[{i}]: Can't be stopped. Current status {}\n",
-                                    process.state
-                                )
-                            }
-                        });
-                    }
+        let mut results = Vec::new();
+        let task_group = tasks
+            .get_mut(name)
+            .ok_or_else(|| Report::task_not_found(name))?;
+        let dependents = running_dependents.join(", ");
+        logger.monit_log(
+            LogLevel::Info,
+            format!("All task in {name} will be stopped after {dependents}"),
+        );
+        for (i, process) in task_group.iter_mut().enumerate() {
+            let result = match process.state {
+                RUNNING(_) | STARTING(_) | UNHEALTHY(_) if process.child.is_some() => {
+                    process.stopping_after = running_dependents.clone();
+                    ProcessResult::ok(name, i, format!("Stopping after {dependents}..."))
                 }
-                Some(index) => match task_group.get_mut(*index) {
-                    None => {
-                        result += &logger.monit_log(format!(
-                            "{name}[{index}]: Can't be stopped, it doesn't exist\n"
-                        ))
+                _ => Self::stop_process(process, name, i),
+            };
+            results.push(Self::log_result(&mut logger, result));
+        }
+        Ok(results)
+    }
+
+    fn stop_task(
+        &mut self,
+        name: &String,
+        num: &Option<usize>,
+    ) -> Result<Vec<ProcessResult>, Report> {
+        let mut tasks = self.tasks.lock().unwrap();
+        let mut logger = self.logger.lock().unwrap();
+        let mut results = Vec::new();
+        let task_group = tasks
+            .get_mut(name)
+            .ok_or_else(|| Report::task_not_found(name))?;
+        match num {
+            None => {
+                logger.monit_log(
+                    LogLevel::Info,
+                    format!("All task in {name} will be stopped"),
+                );
+                for (i, process) in task_group.iter_mut().enumerate() {
+                    let result = Self::stop_process(process, name, i);
+                    results.push(Self::log_result(&mut logger, result));
+                }
+            }
+            Some(index) => {
+                let result = match task_group.get_mut(*index) {
+                    None => ProcessResult::error(
+                        name,
+                        *index,
+                        ErrorKind::ProcessNotFound,
+                        "Can't be stopped, it doesn't exist".to_string(),
+                    ),
+                    Some(task) if !task.waiting_for.is_empty() => {
+                        task.cancel_waiting();
+                        ProcessResult::ok(name, *index, "No longer waiting".to_string())
+                    }
+                    Some(task) if matches!(task.state, BACKOFF(_)) => {
+                        task.state = STOPPED(Some(SystemTime::now()));
+                        ProcessResult::ok(name, *index, "No longer retrying".to_string())
                     }
                     Some(task) => match task.stop() {
-                        Ok(_) => {
-                            result += &logger.monit_log(format!("{name}[{index}]: Stopping...\n"))
-                        }
-                        Err(err) => {
-                            result += &logger
-                                .monit_log(format!("{name}[{index}]: Can't be stopped: {err}\n"))
-                        }
+                        Ok(_) => ProcessResult::ok(name, *index, "Stopping...".to_string()),
+                        Err(err) => ProcessResult::error(
+                            name,
+                            *index,
+                            ErrorKind::StopFailed,
+                            format!("Can't be stopped: {err}"),
+                        ),
                     },
-                },
+                };
+                results.push(Self::log_result(&mut logger, result));
             }
-        } else {
-            result = format!("Can't find \"{name}\" task")
         }
-        result
+        Ok(results)
     }
 
-    fn start_task(&mut self, name: &String, num: &Option<usize>) -> String {
+    fn start_task(
+        &mut self,
+        name: &String,
+        num: &Option<usize>,
+    ) -> Result<Vec<ProcessResult>, Report> {
         let mut tasks = self.tasks.lock().unwrap();
         let mut logger = self.logger.lock().unwrap();
-        let mut result = String::new();
-        if let Some(task_group) = tasks.get_mut(name) {
-            match num {
-                None => {
-                    logger.monit_log(format!("All task in {name} will be started"));
-                    for (i, process) in task_group.iter_mut().enumerate() {
-                        if process.can_be_launched() {
-                            process.restarts_left = process.configuration.start_retries;
-                            if let Err(e_msg) = process.run() {
-                                result += &logger.monit_log(format!(
-                                    "{name}[{i}]: Error during the start: {e_msg}\n"
-                                ));
-                            } else {
-                                result += &logger.monit_log(format!("{name}[{i}]: Starting...\n"))
-                            }
+        let mut results = Vec::new();
+        let missing_dependencies = Self::missing_dependencies(&tasks, name);
+        let task_group = tasks
+            .get_mut(name)
+            .ok_or_else(|| Report::task_not_found(name))?;
+        match num {
+            None => {
+                logger.monit_log(
+                    LogLevel::Info,
+                    format!("All task in {name} will be started"),
+                );
+                for (i, process) in task_group.iter_mut().enumerate() {
+                    let result = if process.can_be_launched() {
+                        process.restarts_left = process.configuration.start_retries;
+                        process.restart_times.clear();
+                        if !missing_dependencies.is_empty() {
+                            process.waiting_for = missing_dependencies.clone();
+                            let waiting_for = missing_dependencies.join(", ");
+                            ProcessResult::ok(name, i, format!("Waiting for {waiting_for}..."))
+                        } else if let Err(e_msg) = process.run() {
+                            ProcessResult::error(
+                                name,
+                                i,
+                                ErrorKind::StartFailed,
+                                format!("Error during the start: {e_msg}"),
+                            )
                         } else {
-                            result += &logger.monit_log(format!(
-                                "{name}[{i}]: Can't be started. Current status {}\n",
-                                process.state
-                            ))
+                            ProcessResult::ok(name, i, "Starting...".to_string())
                         }
-                    }
+                    } else {
+                        ProcessResult::error(
+                            name,
+                            i,
+                            ErrorKind::InvalidState,
+                            format!("Can't be started. Current status {}", process.state),
+                        )
+                    };
+                    results.push(Self::log_result(&mut logger, result));
                 }
-                Some(index) => match task_group.get_mut(*index) {
-                    None => {
-                        result += &logger.monit_log(format!(
-                            "{name}[{index}]: Can't be started, it doesn't exist\n"
-                        ))
+            }
+            Some(index) => {
+                let result = match task_group.get_mut(*index) {
+                    None => ProcessResult::error(
+                        name,
+                        *index,
+                        ErrorKind::ProcessNotFound,
+                        "Can't be started, it doesn't exist".to_string(),
+                    ),
+                    Some(task) if !missing_dependencies.is_empty() => {
+                        task.waiting_for = missing_dependencies.clone();
+                        let waiting_for = missing_dependencies.join(", ");
+                        ProcessResult::ok(name, *index, format!("Waiting for {waiting_for}..."))
                     }
                     Some(task) => match task.run() {
-                        Ok(_) => {
-                            result +=
-                                &logger.monit_log(format!("{name}[{index}]: has been started\n"))
-                        }
-                        Err(err) => {
-                            result += &logger
-                                .monit_log(format!("{name}[{index}]: Can't be launched: {err}\n"))
-                        }
+                        Ok(_) => ProcessResult::ok(name, *index, "has been started".to_string()),
+                        Err(err) => ProcessResult::error(
+                            name,
+                            *index,
+                            ErrorKind::StartFailed,
+                            format!("Can't be launched: {err}"),
+                        ),
                     },
-                },
+                };
+                results.push(Self::log_result(&mut logger, result));
             }
-        } else {
-            result = format!("Can't find \"{name}\" task")
         }
-        result
+        Ok(results)
     }
 
-    fn signal_task(&mut self, signum: u8, task_name: &str, idx: Option<usize>) -> String {
+    fn signal_task(
+        &mut self,
+        signum: u8,
+        task_name: &str,
+        idx: Option<usize>,
+    ) -> Result<Vec<ProcessResult>, Report> {
         let mut tasks = self.tasks.lock().unwrap();
         let mut logger = self.logger.lock().unwrap();
-        match tasks.get_mut(task_name) {
-            Some(task_group) => match idx {
-                Some(idx) => match task_group.get(idx) {
-                    Some(task) => logger.monit_log(task.signal(signum, task_name, idx)),
-                    None => format!("Can't find {task_name}[{idx}] task"),
-                },
-                None => task_group
-                    .iter()
-                    .enumerate()
-                    .map(|(idx, task)| logger.monit_log(task.signal(signum, task_name, idx)))
-                    .collect(),
+        let task_group = tasks
+            .get_mut(task_name)
+            .ok_or_else(|| Report::task_not_found(task_name))?;
+        match idx {
+            Some(idx) => match task_group.get(idx) {
+                Some(task) => Ok(vec![Self::log_result(
+                    &mut logger,
+                    task.signal(signum, task_name, idx),
+                )]),
+                None => Err(Report::error(
+                    ErrorKind::ProcessNotFound,
+                    format!("Can't find {task_name}[{idx}] task"),
+                )),
             },
-            None => format!("Can't find \"{task_name}\" task"),
+            None => Ok(task_group
+                .iter()
+                .enumerate()
+                .map(|(idx, task)| {
+                    Self::log_result(&mut logger, task.signal(signum, task_name, idx))
+                })
+                .collect()),
+        }
+    }
+
+    fn for_all_tasks<F>(&mut self, mut operation: F) -> Report
+    where
+        F: FnMut(&mut Self, &String) -> Result<Vec<ProcessResult>, Report>,
+    {
+        let task_names = Self::task_order(&self.tasks.lock().unwrap());
+        let mut results = Vec::new();
+        for task_name in &task_names {
+            if let Ok(task_results) = operation(self, task_name) {
+                results.extend(task_results);
+            }
+        }
+        Report::Processes { results }
+    }
+
+    fn stop_all_tasks(&mut self) -> Report {
+        let task_names = Self::task_order(&self.tasks.lock().unwrap());
+        let mut results = Vec::new();
+        for task_name in task_names.iter().rev() {
+            if let Ok(task_results) = self.stop_task_after_dependents(task_name) {
+                results.extend(task_results);
+            }
+        }
+        Report::Processes { results }
+    }
+
+    fn relaunch(process: &mut Task, task_name: &str, logger: &mut MutexGuard<Logger>) {
+        if let Err(reason) = process.record_restart() {
+            logger.sth_log(
+                LogLevel::Error,
+                format!(
+                "{task_name}: restart limit reached, {reason}. Status has been changed to fatal."
+            ),
+            );
+            process.state = FATAL(reason);
+            return;
+        }
+        if let Err(err) = process.run() {
+            logger.sth_log(LogLevel::Error, format!("{task_name}: {err}"));
+        }
+    }
+
+    fn retry_later(
+        process: &mut Task,
+        task_name: &str,
+        reason: &str,
+        logger: &mut MutexGuard<Logger>,
+    ) {
+        if process.restarts_left == 0 {
+            process.state = FATAL(reason.to_string());
+            logger.sth_log(
+                LogLevel::Error,
+                format!("{task_name}: No restarts left, status has been changed to fatal."),
+            );
+        } else {
+            let delay = process.backoff_delay();
+            process.restarts_left -= 1;
+            process.state = BACKOFF(SystemTime::now() + delay);
+            logger.sth_log(
+                LogLevel::Info,
+                format!(
+                    "{task_name}: {reason}, status changed to backoff, retrying in {:.1}s",
+                    delay.as_secs_f64()
+                ),
+            );
         }
     }
 
     fn manage_finished_state(
         process: &mut Task,
         task_name: String,
-        exit_code: Option<i32>,
+        status: ExitStatus,
         logger: &mut MutexGuard<Logger>,
     ) {
-        logger.sth_log(format!("{task_name}: exited with status {:?}", exit_code));
-        process.exit_code = exit_code;
-        process.child = None;
+        let record = process.record_exit(&status);
+        logger.sth_log(LogLevel::Info, format!("{task_name}: exited with {record}"));
+        let pid = process.child.take().map(|child| child.id());
         match process.state {
-            STARTING(_) => {
-                process.state = BACKOFF;
-                logger.sth_log(format!(
-                    "{task_name}: Exited too quickly, status changed to backoff"
-                ));
-                if process.restarts_left == 0 {
-                    process.state = FATAL(format!("exited too quickly"));
-                    logger.sth_log(format!(
-                        "{task_name}: No restarts left, status has been changed to fatal."
+            STARTING(_) | RUNNING(_) | UNHEALTHY(_) if !process.stopping_after.is_empty() => {
+                logger.sth_log(
+                    LogLevel::Info,
+                    format!("{task_name}: exited while waiting to be stopped"),
+                );
+                process.stopping_after.clear();
+                process.state = STOPPED(Some(SystemTime::now()));
+            }
+            STARTING(_) => Self::retry_later(process, &task_name, "exited too quickly", logger),
+            UNHEALTHY(_) if process.is_restarting_unhealthy() => {
+                if let Err(reason) = process.record_restart() {
+                    logger.sth_log(LogLevel::Error, format!(
+                        "{task_name}: restart limit reached, {reason}. Status has been changed to fatal."
                     ));
-                } else {
-                    process.restarts_left -= 1;
-                    logger.sth_log(format!("{task_name}: Restarting, exited too quickly"));
-                    if let Err(err) = process.run() {
-                        logger.sth_log(format!("{task_name}: {err}"));
-                    }
+                    process.state = FATAL(reason);
+                    return;
                 }
+                Self::retry_later(process, &task_name, "unhealthy", logger);
             }
-            RUNNING(_) => {
-                match process.configuration.auto_restart {
-                    AutoRestart::True => {
-                        if let Err(err) = process.run() {
-                            logger.sth_log(format!("{task_name}: {err}"));
-                        }
-                        logger.sth_log(format!("{task_name}: Relaunching..."));
-                    }
-                    AutoRestart::False => {
-                        logger.sth_log(format!("{task_name}: auto restart disabled."));
+            RUNNING(_) | UNHEALTHY(_) => match process.configuration.auto_restart {
+                AutoRestart::True => {
+                    logger.sth_log(LogLevel::Info, format!("{task_name}: Relaunching..."));
+                    Self::relaunch(process, &task_name, logger);
+                }
+                AutoRestart::False => {
+                    logger.sth_log(
+                        LogLevel::Info,
+                        format!("{task_name}: auto restart disabled."),
+                    );
+                    process.state = EXITED(SystemTime::now());
+                }
+                AutoRestart::Unexpected => {
+                    if record.expected {
+                        logger.sth_log(LogLevel::Info, format!("{task_name}: program has been finished with expected status, relaunch is not needed"));
                         process.state = EXITED(SystemTime::now());
+                    } else {
+                        logger.sth_log(
+                            LogLevel::Info,
+                            format!(
+                                "{task_name}: {record} is not expected exit status. Relaunching..."
+                            ),
+                        );
+                        Self::relaunch(process, &task_name, logger);
                     }
-                    AutoRestart::Unexpected => match exit_code {
-                        None => {
-                            logger.sth_log(format!(
-                                "{task_name}: unable to access exit status. Relaunching..."
-                            ));
-                            if let Err(err) = process.run() {
-                                logger.sth_log(format!("{task_name}: {err}"));
-                            }
-                        }
-                        Some(code) => {
-                            if process.configuration.exit_codes.contains(&code) {
-                                logger.sth_log(format!("{task_name}: program has been finished with expected status, relaunch is not needed"));
-                                process.state = EXITED(SystemTime::now());
-                            } else {
-                                logger.sth_log(format!("{task_name}: {code} is not expected exit status. Relaunching..."));
-                                if let Err(err) = process.run() {
-                                    logger.sth_log(format!("{task_name}: {err}"));
-                                }
-                            }
-                        }
-                    },
                 }
-            }
+            },
             STOPPING(stopped_at) => {
-                logger.sth_log(format!(
-                    "{task_name}: has stopped by itself after sending a signal"
-                ));
+                logger.sth_log(
+                    LogLevel::Info,
+                    format!("{task_name}: has stopped by itself after sending a signal"),
+                );
+                if let Some(pid) = pid {
+                    if process.kill_leftover_group(pid) {
+                        logger.sth_log(
+                            LogLevel::Info,
+                            format!(
+                                "{task_name}: killed the leftover processes of its process group"
+                            ),
+                        );
+                    }
+                }
                 process.state = STOPPED(Some(stopped_at));
             }
-            _ => logger.log_err(format!(
-                "{} died in unexpected state: {}",
-                task_name, process.state
-            )),
+            _ => logger.log(
+                LogLevel::Error,
+                format!("{} died in unexpected state: {}", task_name, process.state),
+            ),
         }
     }
 
-    fn handle_deprecated_tasks(
-        logger: &Arc<Mutex<Logger>>,
-        deprecated_tasks: &Arc<Mutex<Vec<Task>>>,
-    ) {
-        let mut deprecated_tasks = deprecated_tasks.lock().unwrap();
+    fn reap_exits(
+        tasks: &mut BTreeMap<String, Vec<Task>>,
+        deprecated_tasks: &mut [Task],
+        events: &Arc<Events>,
+        logger: &mut MutexGuard<Logger>,
+    ) -> BTreeSet<String> {
+        let mut exits = events.reap();
+        let mut stopped = BTreeSet::new();
+        if exits.is_empty() {
+            return stopped;
+        }
+        for (name, task_group) in tasks.iter_mut() {
+            for (i, process) in task_group.iter_mut().enumerate() {
+                let Some(status) = process.pid().and_then(|pid| exits.remove(&pid)) else {
+                    continue;
+                };
+                Self::manage_finished_state(process, format!("{name}[{i}]"), status, logger);
+                stopped.insert(name.clone());
+            }
+        }
+        for task in deprecated_tasks.iter_mut() {
+            if task.pid().is_some_and(|pid| exits.remove(&pid).is_some()) {
+                task.child = None;
+            }
+        }
+        stopped
+    }
 
+    fn update_deprecated_tasks(
+        deprecated_tasks: &mut Vec<Task>,
+        logger: &mut MutexGuard<Logger>,
+    ) -> (Option<SystemTime>, Vec<Cgroup>) {
+        let mut deadline = None;
+        let mut released = Vec::new();
         for i in (0..deprecated_tasks.len()).rev() {
-            if let Some(task) = deprecated_tasks.get_mut(i) {
-                match &task.child {
-                    None => {
-                        deprecated_tasks.remove(i);
-                    }
-                    Some(_) => match task.state {
-                        STOPPING(stopped_at) => {
-                            if is_time_elapsed(stopped_at, task.configuration.stop_time) {
-                                let mut logger = logger.lock().unwrap();
-                                if let Err(err) = task.kill() {
-                                    logger.sth_log(format!("Can't kill deprecated task: {}", err));
-                                }
-                            }
-                        }
-                        STOPPED(_) => {
-                            deprecated_tasks.remove(i);
-                        }
-                        _ => {
-                            let _ = task.stop();
+            let task = &mut deprecated_tasks[i];
+            match task.state {
+                _ if task.child.is_none() => {}
+                STOPPING(stopped_at) => {
+                    if is_time_elapsed(stopped_at, task.configuration.stop_time) {
+                        if let Err(err) = task.kill() {
+                            logger.sth_log(
+                                LogLevel::Error,
+                                format!("Can't kill deprecated task: {}", err),
+                            );
                         }
-                    },
+                    }
+                }
+                _ => {
+                    let _ = task.stop();
                 }
             }
+            if task.child.is_none() {
+                released.extend(deprecated_tasks.remove(i).cgroup);
+            } else {
+                deadline = earliest(deadline, task.next_deadline());
+            }
         }
+        released.retain(|cgroup| {
+            !deprecated_tasks
+                .iter()
+                .any(|task| task.cgroup.as_ref() == Some(cgroup))
+        });
+        released.dedup();
+        (deadline, released)
     }
 
-    fn kill_all(&self) {
-        let mut logger = self.logger.lock().unwrap();
-        logger.monit_log("Killing all launched tasks".to_string());
-        for (task_name, process_group) in self.tasks.lock().unwrap().iter_mut() {
-            for (i, task) in process_group.iter_mut().enumerate() {
-                if let Some(_) = task.child {
-                    logger.monit_log(format!("Killing {task_name}[{i}]..."));
-                    if let Err(msg) = task.kill() {
-                        logger.monit_log(msg);
-                    }
+    fn destroy_released_cgroups(
+        tasks: &BTreeMap<String, Vec<Task>>,
+        released: Vec<Cgroup>,
+        logger: &mut MutexGuard<Logger>,
+    ) {
+        for cgroup in released {
+            let is_used = tasks.values().any(|task_group| {
+                task_group.first().and_then(|task| task.cgroup.as_ref()) == Some(&cgroup)
+            });
+            if !is_used {
+                if let Err(err) = cgroup.destroy() {
+                    logger.sth_log(LogLevel::Error, err);
                 }
             }
         }
-        logger.monit_log("Killing all deprecated tasks".to_string());
-        for (i, task) in self.deprecated_tasks.lock().unwrap().iter_mut().enumerate() {
-            if let Some(_) = task.child {
-                logger.monit_log(format!("Killing deprecated task #{i}..."));
-                if let Err(msg) = task.kill() {
-                    logger.monit_log(msg);
+    }
+
+    fn destroy_cgroups(
+        tasks: &BTreeMap<String, Vec<Task>>,
+        deprecated_tasks: &[Task],
+        logger: &mut MutexGuard<Logger>,
+    ) {
+        for (task_name, process_group) in tasks.iter() {
+            if let Some(cgroup) = process_group.first().and_then(|task| task.cgroup.as_ref()) {
+                if let Err(msg) = cgroup.destroy() {
+                    logger.monit_log(LogLevel::Error, format!("{task_name}: {msg}"));
+                }
+            }
+        }
+        for task in deprecated_tasks {
+            if let Some(cgroup) = &task.cgroup {
+                if let Err(msg) = cgroup.destroy() {
+                    logger.monit_log(LogLevel::Error, msg);
                 }
             }
         }
     }
 
-    pub fn control_log_files_limit(
-        logger: &Arc<Mutex<Logger>>,
-        tasks: &Arc<Mutex<BTreeMap<String, Vec<Task>>>>,
+    fn flush_log_messages(
+        tasks: &BTreeMap<String, Vec<Task>>,
+        deprecated_tasks: &[Task],
+        logger: &mut MutexGuard<Logger>,
     ) {
-        for (_, task_group) in tasks.lock().unwrap().iter() {
-            let max_size = task_group[0].configuration.logfile_maxbytes;
-            let logs = task_group[0].control_log_files_limit(max_size);
-            if !logs.is_empty() {
-                let mut logger = logger.lock().unwrap();
-                for log in logs {
-                    logger.sth_log(log);
-                }
+        for task in tasks.values().flatten().chain(deprecated_tasks) {
+            for (level, log) in task.take_log_messages() {
+                logger.sth_log(level, log);
             }
         }
     }
 
-    pub fn track(&self) {
-        let monitor_clone = self.tasks.clone();
-        let deprecated_tasks_clone = self.deprecated_tasks.clone();
-        let tasks_clone = self.tasks.clone();
-        let logger_clone = self.logger.clone();
+    fn kill_cgroup_leftovers(
+        tasks: &BTreeMap<String, Vec<Task>>,
+        deprecated_tasks: &[Task],
+        stopped: &BTreeSet<String>,
+        logger: &mut MutexGuard<Logger>,
+    ) {
+        for name in stopped {
+            let Some(task_group) = tasks.get(name) else {
+                continue;
+            };
+            let Some(cgroup) = task_group.first().and_then(|task| task.cgroup.as_ref()) else {
+                continue;
+            };
+            let is_running = task_group
+                .iter()
+                .chain(deprecated_tasks)
+                .any(|task| task.child.is_some() && task.cgroup.as_ref() == Some(cgroup));
+            if is_running {
+                continue;
+            }
+            match cgroup.kill() {
+                Ok(0) => {}
+                Ok(count) => {
+                    logger.sth_log(
+                        LogLevel::Info,
+                        format!("{name}: killed {count} leftover process(es) of the cgroup"),
+                    );
+                }
+                Err(err) => {
+                    logger.sth_log(LogLevel::Error, format!("{name}: {err}"));
+                }
+            }
+        }
+    }
 
-        thread::spawn(move || loop {
-            Self::handle_deprecated_tasks(&logger_clone, &deprecated_tasks_clone);
-            Self::control_log_files_limit(&logger_clone, &tasks_clone);
-            let mut tasks = monitor_clone.lock().unwrap();
-            let mut logger = logger_clone.lock().unwrap();
-            for (name, task) in tasks.iter_mut() {
-                for (i, process) in task.iter_mut().enumerate() {
-                    match process.state {
-                        STARTING(started_at) => {
-                            if is_time_elapsed(started_at, process.configuration.start_time) {
-                                logger.sth_log(format!("{name}[{i}]: is running now"));
-                                process.state = RUNNING(started_at);
-                            }
-                        }
-                        STOPPING(stopped_at) => {
-                            if is_time_elapsed(stopped_at, process.configuration.stop_time) {
-                                logger.sth_log(format!("{name}[{i}]: Should be killed"));
-                                if let Err(err) = process.kill() {
-                                    logger.sth_log(format!("{name}[{i}]: {err}"));
-                                }
-                            }
-                        }
-                        STOPPED(_) => {
-                            if process.is_manual_restarting {
-                                process.is_manual_restarting = false;
-                                logger.sth_log(format!(
-                                    "{name}[{i}]: Starting after manual restarting"
-                                ));
-                                if let Err(err) = process.run() {
-                                    logger.sth_log(format!("{name}[{i}]: {err}"));
-                                }
-                            }
+    fn update_health(
+        process: &mut Task,
+        task_name: String,
+        started_at: SystemTime,
+        events: &Arc<Events>,
+        logger: &mut MutexGuard<Logger>,
+    ) {
+        match process.check_health(events) {
+            Some(Ok(())) if !matches!(process.state, RUNNING(_)) => {
+                logger.sth_log(
+                    LogLevel::Info,
+                    format!("{task_name}: is healthy, running now"),
+                );
+                process.state = RUNNING(started_at);
+            }
+            Some(Err(err)) => {
+                logger.sth_log(
+                    LogLevel::Info,
+                    format!("{task_name}: health check failed: {err}"),
+                );
+                let is_failing = process
+                    .health
+                    .as_ref()
+                    .is_some_and(|health| health.is_failing());
+                if is_failing && !matches!(process.state, UNHEALTHY(_)) {
+                    logger.sth_log(
+                        LogLevel::Error,
+                        format!("{task_name}: status changed to unhealthy"),
+                    );
+                    process.state = UNHEALTHY(started_at);
+                    if process
+                        .health
+                        .as_ref()
+                        .is_some_and(|health| health.restarts())
+                    {
+                        logger.sth_log(
+                            LogLevel::Info,
+                            format!("{task_name}: Stopping unhealthy process"),
+                        );
+                        if let Err(err) = process.restart_unhealthy() {
+                            logger.sth_log(LogLevel::Error, format!("{task_name}: {err}"));
                         }
-                        _ => {}
                     }
-                    match &mut process.child {
-                        Some(child) => match child.try_wait() {
-                            Ok(Some(status)) => {
-                                Self::manage_finished_state(
-                                    process,
-                                    format!("{name}[{i}]"),
-                                    status.code(),
-                                    &mut logger,
-                                );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn update_process(
+        process: &mut Task,
+        task_name: String,
+        events: &Arc<Events>,
+        logger: &mut MutexGuard<Logger>,
+    ) {
+        match process.state {
+            STARTING(started_at)
+                if is_time_elapsed(started_at, process.configuration.start_time) =>
+            {
+                if process.health.is_none() {
+                    logger.sth_log(LogLevel::Info, format!("{task_name}: is running now"));
+                    process.state = RUNNING(started_at);
+                } else {
+                    Self::update_health(process, task_name, started_at, events, logger);
+                }
+            }
+            RUNNING(started_at) | UNHEALTHY(started_at) => {
+                if matches!(process.state, RUNNING(_))
+                    && process.restarts_left < process.configuration.start_retries
+                    && is_time_elapsed(started_at, process.configuration.backoff_reset)
+                {
+                    logger.sth_log(
+                        LogLevel::Info,
+                        format!(
+                            "{task_name}: running for {}s, retry counter reset",
+                            process.configuration.backoff_reset
+                        ),
+                    );
+                    process.restarts_left = process.configuration.start_retries;
+                }
+                match process.health.as_ref().and_then(|health| health.stopped_at) {
+                    Some(stopped_at)
+                        if is_time_elapsed(stopped_at, process.configuration.stop_time) =>
+                    {
+                        logger.sth_log(LogLevel::Info, format!("{task_name}: Should be killed"));
+                        match process.kill_unhealthy() {
+                            Ok(status) => {
+                                Self::manage_finished_state(process, task_name, status, logger);
                             }
-                            Ok(None) => {}
-                            Err(e) => logger.log_err(format!("Error attempting to wait: {:?}", e)),
-                        },
-                        None => {
-                            if process.configuration.auto_start && process.state == STOPPED(None) {
-                                logger.sth_log(format!("Auto starting {name}[{i}]"));
-                                if let Err(error_msg) = process.run() {
-                                    logger.sth_log(format!("{name}[{i}]: {error_msg}"));
-                                }
+                            Err(err) => {
+                                logger.sth_log(LogLevel::Error, format!("{task_name}: {err}"));
                             }
                         }
                     }
+                    Some(_) => {}
+                    None => Self::update_health(process, task_name, started_at, events, logger),
                 }
             }
-            drop(logger);
-            drop(tasks);
-            thread::sleep(Duration::from_millis(100));
+            BACKOFF(until) if SystemTime::now() >= until => {
+                let reason = match process.is_restarting_unhealthy() {
+                    true => "was unhealthy",
+                    false => "exited too quickly",
+                };
+                logger.sth_log(LogLevel::Info, format!("{task_name}: Restarting, {reason}"));
+                if let Err(err) = process.run() {
+                    logger.sth_log(LogLevel::Error, format!("{task_name}: {err}"));
+                }
+            }
+            STOPPING(stopped_at) => {
+                if is_time_elapsed(stopped_at, process.configuration.stop_time) {
+                    logger.sth_log(LogLevel::Info, format!("{task_name}: Should be killed"));
+                    if let Err(err) = process.kill() {
+                        logger.sth_log(LogLevel::Error, format!("{task_name}: {err}"));
+                    }
+                }
+            }
+            STOPPED(_) => {
+                if process.is_manual_restarting {
+                    process.is_manual_restarting = false;
+                    logger.sth_log(
+                        LogLevel::Info,
+                        format!("{task_name}: Starting after manual restarting"),
+                    );
+                    if let Err(err) = process.run() {
+                        logger.sth_log(LogLevel::Error, format!("{task_name}: {err}"));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_waiting_to_start(process: &Task) -> bool {
+        process.child.is_none()
+            && (!process.waiting_for.is_empty()
+                || (process.configuration.auto_start && process.state == STOPPED(None)))
+    }
+
+    fn start_waiting_process(
+        process: &mut Task,
+        task_name: String,
+        missing_dependencies: &[String],
+        logger: &mut MutexGuard<Logger>,
+    ) {
+        if !missing_dependencies.is_empty() {
+            if process.waiting_for != missing_dependencies {
+                logger.sth_log(
+                    LogLevel::Info,
+                    format!(
+                        "{task_name}: waiting for {}",
+                        missing_dependencies.join(", ")
+                    ),
+                );
+                process.waiting_for = missing_dependencies.to_vec();
+            }
+            return;
+        }
+        if process.waiting_for.is_empty() {
+            logger.sth_log(LogLevel::Info, format!("Auto starting {task_name}"));
+        } else {
+            logger.sth_log(
+                LogLevel::Info,
+                format!("{task_name}: dependencies are running, starting"),
+            );
+        }
+        process.waiting_for.clear();
+        if let Err(error_msg) = process.run() {
+            logger.sth_log(LogLevel::Error, format!("{task_name}: {error_msg}"));
+        }
+    }
+
+    fn stop_after_dependents(
+        process: &mut Task,
+        task_name: String,
+        running_dependents: &[String],
+        logger: &mut MutexGuard<Logger>,
+    ) {
+        if !running_dependents.is_empty() {
+            process.stopping_after = running_dependents.to_vec();
+            return;
+        }
+        logger.sth_log(
+            LogLevel::Info,
+            format!("{task_name}: dependents have stopped, stopping"),
+        );
+        if let Err(err) = process.stop() {
+            logger.sth_log(LogLevel::Error, format!("{task_name}: {err}"));
+        }
+    }
+
+    fn update_tasks(
+        tasks: &mut BTreeMap<String, Vec<Task>>,
+        events: &Arc<Events>,
+        logger: &mut MutexGuard<Logger>,
+        stopped: &mut BTreeSet<String>,
+    ) -> Option<SystemTime> {
+        let mut deadline = None;
+        for name in Self::task_order(tasks) {
+            let Some(task_group) = tasks.get(&name) else {
+                continue;
+            };
+            let missing_dependencies = match task_group.iter().any(Self::is_waiting_to_start) {
+                true => Self::missing_dependencies(tasks, &name),
+                false => Vec::new(),
+            };
+            let running_dependents = match task_group
+                .iter()
+                .any(|process| !process.stopping_after.is_empty())
+            {
+                true => Self::running_dependents(tasks, &name),
+                false => Vec::new(),
+            };
+            let Some(task_group) = tasks.get_mut(&name) else {
+                continue;
+            };
+            for (i, process) in task_group.iter_mut().enumerate() {
+                if process
+                    .next_deadline()
+                    .is_some_and(|deadline| deadline <= SystemTime::now())
+                {
+                    let had_child = process.child.is_some();
+                    Self::update_process(process, format!("{name}[{i}]"), events, logger);
+                    if had_child && process.child.is_none() {
+                        stopped.insert(name.clone());
+                    }
+                }
+                if !process.stopping_after.is_empty() {
+                    Self::stop_after_dependents(
+                        process,
+                        format!("{name}[{i}]"),
+                        &running_dependents,
+                        logger,
+                    );
+                }
+                if Self::is_waiting_to_start(process) {
+                    Self::start_waiting_process(
+                        process,
+                        format!("{name}[{i}]"),
+                        &missing_dependencies,
+                        logger,
+                    );
+                }
+                deadline = earliest(deadline, process.next_deadline());
+            }
+        }
+        deadline
+    }
+
+    fn update(
+        logger: &Arc<Mutex<Logger>>,
+        tasks: &Arc<Mutex<BTreeMap<String, Vec<Task>>>>,
+        deprecated_tasks: &Arc<Mutex<Vec<Task>>>,
+        events: &Arc<Events>,
+        is_shutting_down: &AtomicBool,
+    ) -> Option<SystemTime> {
+        let mut tasks = tasks.lock().unwrap();
+        let mut logger = logger.lock().unwrap();
+        let mut deprecated_tasks = deprecated_tasks.lock().unwrap();
+        Self::flush_log_messages(&tasks, &deprecated_tasks, &mut logger);
+        let mut stopped = Self::reap_exits(&mut tasks, &mut deprecated_tasks, events, &mut logger);
+        let deadline = Self::update_tasks(&mut tasks, events, &mut logger, &mut stopped);
+        let (deprecated_deadline, released) =
+            Self::update_deprecated_tasks(&mut deprecated_tasks, &mut logger);
+        Self::kill_cgroup_leftovers(&tasks, &deprecated_tasks, &stopped, &mut logger);
+        Self::destroy_released_cgroups(&tasks, released, &mut logger);
+        if is_shutting_down.load(Ordering::SeqCst)
+            && tasks
+                .values()
+                .flatten()
+                .chain(deprecated_tasks.iter())
+                .all(|task| task.child.is_none())
+        {
+            logger.monit_log(
+                LogLevel::Info,
+                "All tasks have stopped, exiting".to_string(),
+            );
+            Self::destroy_cgroups(&tasks, &deprecated_tasks, &mut logger);
+            remove_and_exit(0);
+        }
+        earliest(deadline, deprecated_deadline)
+    }
+
+    pub fn track(&self) {
+        let deprecated_tasks = self.deprecated_tasks.clone();
+        let tasks = self.tasks.clone();
+        let logger = self.logger.clone();
+        let events = self.events.clone();
+        let is_shutting_down = self.is_shutting_down.clone();
+
+        thread::spawn(move || loop {
+            let deadline = Self::update(
+                &logger,
+                &tasks,
+                &deprecated_tasks,
+                &events,
+                &is_shutting_down,
+            );
+            events.wait(deadline);
         });
     }
 
-    pub fn handle_action(&mut self, action: Action) -> Respond {
-        match action {
-            Action::Clear(task_name) => Respond::Message(self.clear_logs(&task_name)),
-            Action::Config(task_name) => match self.get_task_json_config_by_name(&task_name) {
-                None => Respond::Message(format!("Can't find \"{task_name}\" task")),
-                Some(task) => Respond::Message(format!("{task_name}: {task}")),
-            },
+    fn shut_down(&mut self) {
+        self.logger.lock().unwrap().monit_log(
+            LogLevel::Info,
+            "Stopping all tasks before exiting".to_string(),
+        );
+        self.is_shutting_down.store(true, Ordering::SeqCst);
+        for process in self.tasks.lock().unwrap().values_mut().flatten() {
+            process.is_manual_restarting = false;
+        }
+        self.stop_all_tasks();
+    }
+
+    pub fn set_access(&mut self, access: Access) {
+        self.access = access;
+    }
+
+    pub fn set_sources(&mut self, sources: BTreeMap<String, String>) {
+        self.sources = sources;
+    }
+
+    fn authorize(&self, peer: &Peer, action: &Action) -> Result<(), String> {
+        let task_names: Vec<String> = self.tasks.lock().unwrap().keys().cloned().collect();
+        self.access.authorize(peer, action, &task_names)
+    }
+
+    pub fn handle_action(&mut self, action: Action, peer: &Peer) -> Respond {
+        if let Err(err) = self.authorize(peer, &action) {
+            let mut logger = self.logger.lock().unwrap();
+            return Respond::Report(Report::error(
+                ErrorKind::PermissionDenied,
+                logger.monit_log(LogLevel::Info, err),
+            ));
+        }
+        if self.is_shutting_down.load(Ordering::SeqCst)
+            && matches!(
+                action,
+                Action::Restart(_) | Action::Shutdown | Action::Start(_) | Action::Update(_)
+            )
+        {
+            return Respond::Report(Report::error(
+                ErrorKind::InvalidState,
+                "taskmasterd is shutting down".to_string(),
+            ));
+        }
+        let respond = self.dispatch_action(action);
+        self.events.wake();
+        respond
+    }
+
+    fn processes_report(result: Result<Vec<ProcessResult>, Report>) -> Report {
+        match result {
+            Ok(results) => Report::Processes { results },
+            Err(report) => report,
+        }
+    }
+
+    fn dispatch_action(&mut self, action: Action) -> Respond {
+        Respond::Report(match action {
+            Action::Clear(task_name, index) => self.clear_logs(&task_name, index),
+            Action::Config(task_name) => self.get_task_config(&task_name),
             Action::GetHttpLoggingStatus => {
-                Respond::Message(self.logger.lock().unwrap().get_http_logging_status())
+                Report::info(self.logger.lock().unwrap().get_http_logging_status())
             }
             Action::HttpLogging(port) => {
                 let mut logger = self.logger.lock().unwrap();
-                return if let Some(port) = port {
-                    Respond::Message(match logger.enable_http_logging(port) {
-                        Ok(_) => "connected".to_string(),
-                        Err(err) => err,
-                    })
+                if let Some(port) = port {
+                    match logger.enable_http_logging(port) {
+                        Ok(_) => Report::info("connected".to_string()),
+                        Err(err) => Report::error(ErrorKind::HttpLogging, err),
+                    }
                 } else {
-                    Respond::Message(logger.disable_http_logging())
-                };
+                    Report::info(logger.disable_http_logging())
+                }
             }
-            Action::Maintail(arg) => match arg {
-                TailType::Stream(num_lines) => Respond::MaintailStream(num_lines),
-                TailType::Fixed(num_lines) => {
-                    Respond::Message(self.logger.lock().unwrap().get_history(num_lines).join(""))
+            Action::Maintail(arg) => {
+                return match arg {
+                    TailType::Stream(num_lines) => Respond::MaintailStream(num_lines),
+                    TailType::Fixed(num_lines) => Respond::Message(
+                        self.logger.lock().unwrap().get_history(num_lines).join(""),
+                    ),
                 }
-            },
+            }
             Action::Restart(arg) => match arg {
-                Some((task_name, num)) => Respond::Message(self.restart_task(&task_name, &num)),
+                Some((task_name, num)) => {
+                    Self::processes_report(self.restart_task(&task_name, &num))
+                }
                 None => {
-                    let tasks = self
-                        .tasks
-                        .lock()
-                        .unwrap()
-                        .keys()
-                        .cloned()
-                        .collect::<Vec<String>>();
-                    Respond::Message(
-                        tasks
-                            .iter()
-                            .map(|task_name| self.restart_task(&task_name, &None))
-                            .collect(),
-                    )
+                    self.for_all_tasks(|monitor, task_name| monitor.restart_task(task_name, &None))
                 }
             },
             Action::Shutdown => {
-                self.kill_all();
-                remove_and_exit(0)
+                self.shut_down();
+                return Respond::Shutdown;
             }
             Action::Signal(signum, task_name, idx) => {
-                Respond::Message(self.signal_task(signum, &task_name, idx))
+                Self::processes_report(self.signal_task(signum, &task_name, idx))
             }
             Action::Start(arg) => match arg {
-                Some((task_name, num)) => Respond::Message(self.start_task(&task_name, &num)),
+                Some((task_name, num)) => Self::processes_report(self.start_task(&task_name, &num)),
                 None => {
-                    let tasks = self
-                        .tasks
-                        .lock()
-                        .unwrap()
-                        .keys()
-                        .cloned()
-                        .collect::<Vec<String>>();
-                    Respond::Message(
-                        tasks
-                            .iter()
-                            .map(|task_name| self.start_task(&task_name, &None))
-                            .collect(),
-                    )
+                    self.for_all_tasks(|monitor, task_name| monitor.start_task(task_name, &None))
                 }
             },
-            Action::Status(status) => Respond::Message(self.get_task_status(status)),
+            Action::Status(status) => self.get_task_status(status),
             Action::Stop(arg) => match arg {
-                Some((task_name, num)) => Respond::Message(self.stop_task(&task_name, &num)),
-                None => {
-                    let tasks = self
-                        .tasks
-                        .lock()
-                        .unwrap()
-                        .keys()
-                        .cloned()
-                        .collect::<Vec<String>>();
-                    Respond::Message(
-                        tasks
-                            .iter()
-                            .map(|task_name| self.stop_task(&task_name, &None))
-                            .collect(),
-                    )
-                }
+                Some((task_name, num)) => Self::processes_report(self.stop_task(&task_name, &num)),
+                None => self.stop_all_tasks(),
             },
-            Action::Tail(task_name, output_type, tail_type) => {
+            Action::Tail(task_name, index, output_type, tail_type) => {
                 let tasks = self.tasks.lock().unwrap();
-                if let Some(task) = tasks.get(&task_name).unwrap_or(&Vec::new()).get(0) {
+                let index = index.unwrap_or(0);
+                let task_group = tasks.get(&task_name);
+                if let Some(task) = task_group.and_then(|task_group| task_group.get(index)) {
                     let filename = match output_type {
                         OutputType::Stdout => task.configuration.stdout.clone(),
                         OutputType::Stderr => task.configuration.stderr.clone(),
                     };
+                    let buffer = task.output_buffer(&output_type).clone();
 
-                    if let Some(filename) = filename {
-                        match tail_type {
-                            TailType::Stream(num_lines) => Respond::Tail(filename, num_lines, true),
-                            TailType::Fixed(num_lines) => Respond::Tail(filename, num_lines, false),
-                        }
-                    } else {
-                        Respond::Message(format!("Can't find {output_type} for {task_name}"))
+                    if output_type == OutputType::Stderr && task.configuration.redirect_stderr {
+                        return Respond::Report(Report::error(
+                            ErrorKind::LogNotConfigured,
+                            format!("stderr of {task_name} is merged into its stdout"),
+                        ));
                     }
+                    if task.configuration.captures_output(&filename) {
+                        return match (tail_type, filename) {
+                            (TailType::Stream(num_lines), _) => {
+                                Respond::TailStream(buffer, num_lines)
+                            }
+                            (TailType::Fixed(num_lines), Some(filename)) => {
+                                Respond::Tail(filename, num_lines)
+                            }
+                            (TailType::Fixed(num_lines), None) => {
+                                Respond::Message(buffer.lock().unwrap().tail(num_lines).0)
+                            }
+                        };
+                    }
+                    Report::error(
+                        ErrorKind::LogNotConfigured,
+                        format!("Can't find {output_type} for {task_name}"),
+                    )
+                } else if task_group.is_some() {
+                    Report::error(
+                        ErrorKind::ProcessNotFound,
+                        format!("Can't find process {index} of {task_name}"),
+                    )
                 } else {
-                    Respond::Message(format!("Can't find task {task_name}"))
+                    Report::error(
+                        ErrorKind::TaskNotFound,
+                        format!("Can't find task {task_name}"),
+                    )
                 }
             }
             Action::Update(arg) => {
@@ -657,10 +1401,66 @@ impl Monitor {
                     self.config_path = config_path;
                 }
                 match Configuration::from_yml(self.config_path.clone(), self.logger.clone()) {
-                    Ok(conf) => Respond::Message(self.update_configuration(conf)),
-                    Err(err_msg) => Respond::Message(format!("{err_msg}")),
+                    Ok(conf) => {
+                        self.access = conf.access;
+                        self.sources = conf.sources;
+                        Report::Update {
+                            changes: self.update_configuration(conf.tasks),
+                        }
+                    }
+                    Err(err_msg) => Report::error(ErrorKind::InvalidConfiguration, err_msg),
                 }
             }
-        }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::{Health, HealthCheck};
+    use crate::settings::LogRetention;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn backs_off_unhealthy_processes_until_fatal() {
+        let path = std::env::temp_dir().join(format!("taskmaster-monitor-{}", std::process::id()));
+        let logger = Logger::new(
+            &path.display().to_string(),
+            LogRetention::Truncate,
+            LogLevel::Error,
+        )
+        .unwrap();
+        let logger = Mutex::new(logger);
+        let check = HealthCheck {
+            tcp: Some("127.0.0.1:80".to_string()),
+            restart: true,
+            ..HealthCheck::default()
+        };
+        let configuration = Configuration {
+            start_retries: 1,
+            backoff_jitter: 0,
+            health_check: Some(check.clone()),
+            ..Configuration::default()
+        };
+        let mut task = Task::new(&configuration, "web", 0, &Arc::new(Events::new()));
+        let exit_unhealthy = |task: &mut Task| {
+            let mut health = Health::new(check.clone());
+            health.stopped_at = Some(SystemTime::now());
+            task.health = Some(health);
+            task.state = UNHEALTHY(SystemTime::now());
+            Monitor::manage_finished_state(
+                task,
+                "web[0]".to_string(),
+                ExitStatus::from_raw(libc::SIGKILL),
+                &mut logger.lock().unwrap(),
+            );
+        };
+        exit_unhealthy(&mut task);
+        assert!(matches!(task.state, BACKOFF(_)), "{}", task.state);
+        assert_eq!(task.restarts_left, 0);
+        exit_unhealthy(&mut task);
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(task.state, FATAL("unhealthy".to_string()));
     }
 }
@@ -0,0 +1,471 @@
+use crate::events::Events;
+use crate::log_file::LogFile;
+use crate::logger::http_request;
+use crate::settings::LogLevel;
+use crate::utils::connect_tcp;
+use std::collections::VecDeque;
+use std::ffi::CString;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const READ_BUFFER_SIZE: usize = 8192;
+const DELIVERY_QUEUE_SIZE: usize = 1024;
+const HTTP_TIMEOUT: Duration = Duration::from_secs(5);
+const CONTENT_LENGTH_HEADER: &str = "content-length:";
+
+pub type OutputLine = (usize, String);
+
+#[derive(Clone)]
+pub struct LogMessages {
+    messages: Arc<Mutex<Vec<(LogLevel, String)>>>,
+    events: Arc<Events>,
+}
+
+pub struct OutputBuffer {
+    lines: VecDeque<OutputLine>,
+    idx: usize,
+    capacity: usize,
+}
+
+pub struct Delivery {
+    sender: SyncSender<Vec<u8>>,
+    error: Arc<Mutex<Option<String>>>,
+    is_dropping: bool,
+}
+
+struct HttpClient {
+    target: String,
+    stream: Option<BufReader<TcpStream>>,
+}
+
+pub enum Sink {
+    File(LogFile),
+    Buffer(Arc<Mutex<OutputBuffer>>),
+    Syslog(Delivery),
+    Http(Delivery),
+}
+
+pub struct Decoration {
+    pub timestamps: bool,
+    pub prefix: Option<String>,
+}
+
+impl LogMessages {
+    pub fn new(events: Arc<Events>) -> LogMessages {
+        LogMessages {
+            messages: Arc::new(Mutex::new(Vec::new())),
+            events,
+        }
+    }
+
+    fn push(&self, level: LogLevel, message: String) {
+        self.messages.lock().unwrap().push((level, message));
+        self.events.wake();
+    }
+
+    pub fn take(&self) -> Vec<(LogLevel, String)> {
+        std::mem::take(&mut *self.messages.lock().unwrap())
+    }
+}
+
+impl OutputBuffer {
+    pub fn new(capacity: usize) -> OutputBuffer {
+        OutputBuffer {
+            lines: VecDeque::with_capacity(capacity),
+            idx: 0,
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+        }
+        self.idx += 1;
+        self.lines.push_back((self.idx, line));
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+    }
+
+    pub fn tail(&self, num_lines: Option<usize>) -> (String, usize) {
+        let skipped = num_lines.map_or(0, |num| self.lines.len().saturating_sub(num));
+        let output = self
+            .lines
+            .iter()
+            .skip(skipped)
+            .map(|(_, line)| line.as_str());
+        (output.collect(), self.idx)
+    }
+
+    pub fn since(&self, last_idx: usize) -> (String, usize) {
+        let output = self
+            .lines
+            .iter()
+            .filter(|(idx, _)| *idx > last_idx)
+            .map(|(_, line)| line.as_str());
+        (output.collect(), self.idx)
+    }
+}
+
+impl Delivery {
+    fn spawn<F>(mut deliver: F) -> Delivery
+    where
+        F: FnMut(&[u8]) -> Result<(), String> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::sync_channel::<Vec<u8>>(DELIVERY_QUEUE_SIZE);
+        let error = Arc::new(Mutex::new(None));
+        let delivery_error = error.clone();
+        thread::spawn(move || {
+            for line in receiver {
+                if let Err(err) = deliver(&line) {
+                    *delivery_error.lock().unwrap() = Some(err);
+                    break;
+                }
+            }
+        });
+        Delivery {
+            sender,
+            error,
+            is_dropping: false,
+        }
+    }
+
+    fn send(&mut self, line: &[u8]) -> Result<Option<String>, String> {
+        if let Some(err) = self.error.lock().unwrap().take() {
+            return Err(err);
+        }
+        match self.sender.try_send(line.to_vec()) {
+            Ok(()) if self.is_dropping => {
+                self.is_dropping = false;
+                Ok(Some("delivery caught up".to_string()))
+            }
+            Ok(()) => Ok(None),
+            Err(TrySendError::Full(_)) if self.is_dropping => Ok(None),
+            Err(TrySendError::Full(_)) => {
+                self.is_dropping = true;
+                Ok(Some("delivery is too slow, dropping output".to_string()))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(self
+                .error
+                .lock()
+                .unwrap()
+                .take()
+                .unwrap_or("delivery stopped".to_string())),
+        }
+    }
+}
+
+impl HttpClient {
+    fn connect(&mut self) -> Result<&mut BufReader<TcpStream>, String> {
+        if self.stream.is_none() {
+            let stream = connect_tcp(&self.target, HTTP_TIMEOUT)?;
+            stream
+                .set_write_timeout(Some(HTTP_TIMEOUT))
+                .and_then(|_| stream.set_read_timeout(Some(HTTP_TIMEOUT)))
+                .map_err(|e| format!("can't set timeouts on {}: {e}", self.target))?;
+            self.stream = Some(BufReader::new(stream));
+        }
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    fn read_response(stream: &mut BufReader<TcpStream>) -> io::Result<()> {
+        let mut content_length = 0;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stream.read_line(&mut line)? == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let header = line.trim_end().to_ascii_lowercase();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(length) = header.strip_prefix(CONTENT_LENGTH_HEADER) {
+                content_length = length.trim().parse().unwrap_or(0);
+            }
+        }
+        io::copy(&mut stream.take(content_length), &mut io::sink())?;
+        Ok(())
+    }
+
+    fn send(&mut self, request: &str) -> Result<(), String> {
+        let target = self.target.clone();
+        let stream = self.connect()?;
+        let result = stream
+            .get_mut()
+            .write_all(request.as_bytes())
+            .and_then(|_| Self::read_response(stream));
+        if result.is_err() {
+            self.stream = None;
+        }
+        result.map_err(|e| format!("can't write output in {target}: {e}"))
+    }
+
+    fn post(&mut self, line: &[u8]) -> Result<(), String> {
+        let request = http_request(&self.target, &String::from_utf8_lossy(line));
+        let was_connected = self.stream.is_some();
+        match self.send(&request) {
+            Err(_) if was_connected => self.send(&request),
+            result => result,
+        }
+    }
+}
+
+impl Sink {
+    pub fn syslog(is_stderr: bool) -> Sink {
+        let level = if is_stderr {
+            libc::LOG_ERR
+        } else {
+            libc::LOG_INFO
+        };
+        let priority = libc::LOG_DAEMON | level;
+        Sink::Syslog(Delivery::spawn(move |line| {
+            let message: Vec<u8> = line
+                .iter()
+                .copied()
+                .filter(|&byte| byte != 0 && byte != b'\n')
+                .collect();
+            let message = CString::new(message).unwrap_or_default();
+            unsafe {
+                libc::syslog(priority, c"%s".as_ptr(), message.as_ptr());
+            }
+            Ok(())
+        }))
+    }
+
+    pub fn http(target: &str) -> Sink {
+        let mut client = HttpClient {
+            target: target.to_string(),
+            stream: None,
+        };
+        Sink::Http(Delivery::spawn(move |line| client.post(line)))
+    }
+
+    fn write(&mut self, line: &[u8]) -> Result<Option<String>, String> {
+        match self {
+            Sink::File(log_file) => log_file.write(line),
+            Sink::Buffer(buffer) => {
+                let line = String::from_utf8_lossy(line).into_owned();
+                buffer.lock().unwrap().push(line);
+                Ok(None)
+            }
+            Sink::Syslog(delivery) | Sink::Http(delivery) => delivery.send(line),
+        }
+    }
+}
+
+fn get_timestamp() -> String {
+    let since_the_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::from_secs(0));
+    let now_in_sec = since_the_epoch.as_secs();
+    let hours = (now_in_sec % (24 * 3600)) / 3600;
+    let minutes = (now_in_sec % 3600) / 60;
+    let seconds = now_in_sec % 60;
+    format!(
+        "[{:02}:{:02}:{:02}.{:03}] ",
+        hours,
+        minutes,
+        seconds,
+        since_the_epoch.subsec_millis()
+    )
+}
+
+impl Decoration {
+    fn apply(&self, line: &[u8]) -> Vec<u8> {
+        let mut decorated = Vec::with_capacity(line.len());
+        if self.timestamps {
+            decorated.extend_from_slice(get_timestamp().as_bytes());
+        }
+        if let Some(prefix) = &self.prefix {
+            decorated.extend_from_slice(format!("[{prefix}] ").as_bytes());
+        }
+        decorated.extend_from_slice(line);
+        decorated
+    }
+}
+
+fn forward(
+    label: &str,
+    line: &[u8],
+    decoration: &Decoration,
+    sinks: &mut Vec<Sink>,
+    messages: &LogMessages,
+) {
+    let line = decoration.apply(line);
+    sinks.retain_mut(|sink| match sink.write(&line) {
+        Ok(None) => true,
+        Ok(Some(message)) => {
+            messages.push(LogLevel::Info, format!("{label}: {message}"));
+            true
+        }
+        Err(message) => {
+            let is_http = matches!(sink, Sink::Http(_));
+            messages.push(
+                LogLevel::Error,
+                if is_http {
+                    format!("{label}: {message}, disabling...")
+                } else {
+                    format!("{label}: {message}")
+                },
+            );
+            !is_http
+        }
+    });
+}
+
+pub fn spawn_reader(
+    label: String,
+    mut source: impl Read + Send + 'static,
+    decoration: Decoration,
+    mut sinks: Vec<Sink>,
+    messages: LogMessages,
+) {
+    thread::spawn(move || {
+        let mut buffer = [0u8; READ_BUFFER_SIZE];
+        let mut pending: Vec<u8> = Vec::new();
+        loop {
+            let read = match source.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    messages.push(
+                        LogLevel::Error,
+                        format!("{label}: Failed to read output: {e}"),
+                    );
+                    break;
+                }
+            };
+            pending.extend_from_slice(&buffer[..read]);
+            let mut start = 0;
+            while let Some(end) = pending[start..].iter().position(|&byte| byte == b'\n') {
+                forward(
+                    &label,
+                    &pending[start..=start + end],
+                    &decoration,
+                    &mut sinks,
+                    &messages,
+                );
+                start += end + 1;
+            }
+            pending.drain(..start);
+            if pending.len() >= READ_BUFFER_SIZE {
+                forward(&label, &pending, &decoration, &mut sinks, &messages);
+                pending.clear();
+            }
+        }
+        if !pending.is_empty() {
+            forward(&label, &pending, &decoration, &mut sinks, &messages);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    fn send_until_error(delivery: &mut Delivery) -> String {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if let Err(err) = delivery.send(b"line\n") {
+                return err;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the delivery never failed");
+    }
+
+    #[test]
+    fn buffer_keeps_the_last_lines() {
+        let mut buffer = OutputBuffer::new(3);
+        for line in ["a\n", "b\n", "c\n", "d\n"] {
+            buffer.push(line.to_string());
+        }
+        assert_eq!(buffer.tail(None), ("b\nc\nd\n".to_string(), 4));
+        assert_eq!(buffer.tail(Some(1)), ("d\n".to_string(), 4));
+        assert_eq!(buffer.since(3), ("d\n".to_string(), 4));
+        assert_eq!(buffer.since(0), ("b\nc\nd\n".to_string(), 4));
+        buffer.clear();
+        buffer.push("e\n".to_string());
+        assert_eq!(buffer.since(4), ("e\n".to_string(), 5));
+        let mut disabled = OutputBuffer::new(0);
+        disabled.push("ignored\n".to_string());
+        assert_eq!(disabled.tail(None), (String::new(), 0));
+    }
+
+    #[test]
+    fn delivery_reports_errors() {
+        let mut delivery = Delivery::spawn(|_| Err("boom".to_string()));
+        assert_eq!(send_until_error(&mut delivery), "boom");
+    }
+
+    #[test]
+    fn delivery_drops_lines_when_full() {
+        let (unblock, blocked) = mpsc::channel::<()>();
+        let blocked = Mutex::new(blocked);
+        let mut delivery = Delivery::spawn(move |_| {
+            let _ = blocked.lock().unwrap().recv();
+            Ok(())
+        });
+        let mut messages = Vec::new();
+        for _ in 0..DELIVERY_QUEUE_SIZE + 10 {
+            messages.push(delivery.send(b"line\n").unwrap());
+        }
+        let warnings: Vec<String> = messages.into_iter().flatten().collect();
+        assert_eq!(warnings, ["delivery is too slow, dropping output"]);
+        drop(unblock);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while delivery.send(b"line\n").unwrap().is_none() {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn http_reuses_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut bodies = Vec::new();
+            for _ in 0..2 {
+                let mut length = 0;
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 0 && line != "\r\n" {
+                    if let Some(value) = line.strip_prefix("Content-Length: ") {
+                        length = value.trim().parse().unwrap();
+                    }
+                    line.clear();
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                bodies.push(String::from_utf8(body).unwrap());
+                reader
+                    .get_mut()
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                    .unwrap();
+            }
+            bodies
+        });
+        let mut client = HttpClient {
+            target,
+            stream: None,
+        };
+        client.post(b"first\n").unwrap();
+        client.post(b"second\n").unwrap();
+        assert_eq!(server.join().unwrap(), ["first\n", "second\n"]);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::action::Action;
+use crate::report::Report;
+use serde::{Deserialize, Serialize};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const MAX_FRAME_SIZE: usize = 16 << 20;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Deserialize)]
+pub struct Request {
+    pub version: u32,
+    pub id: u64,
+    pub action: Action,
+    #[serde(default)]
+    pub format: Format,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Error,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Payload<'a> {
+    Message(&'a str),
+    Result(&'a Report),
+}
+
+#[derive(Serialize)]
+pub struct Response<'a> {
+    pub version: u32,
+    pub id: u64,
+    pub status: Status,
+    pub payload: Payload<'a>,
+    pub more: bool,
+}
+
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 4];
+    match reader.read_exact(&mut header) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let length = u32::from_be_bytes(header) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("frame of {length} bytes is bigger than {MAX_FRAME_SIZE}"),
+        ));
+    }
+    let mut frame = vec![0u8; length];
+    reader.read_exact(&mut frame)?;
+    Ok(Some(frame))
+}
+
+pub fn write_frame<W: Write>(writer: &mut W, frame: &[u8]) -> io::Result<()> {
+    let length = u32::try_from(frame.len())
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "frame is too big"))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(frame)
+}
+
+pub fn parse_request(frame: &[u8]) -> Result<Request, (u64, String)> {
+    let value: serde_json::Value =
+        serde_json::from_slice(frame).map_err(|error| (0, format!("Invalid request: {error}")))?;
+    let id = value.get("id").and_then(|id| id.as_u64()).unwrap_or(0);
+    let request: Request =
+        serde_json::from_value(value).map_err(|error| (id, format!("Unknown action: {error}")))?;
+    if request.version != PROTOCOL_VERSION {
+        return Err((
+            id,
+            format!(
+                "Unsupported protocol version {}, expected {PROTOCOL_VERSION}",
+                request.version
+            ),
+        ));
+    }
+    Ok(request)
+}
+
+pub fn is_framed(stream: &UnixStream) -> io::Result<Option<bool>> {
+    let mut byte = [0u8; 1];
+    let ret = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            byte.as_mut_ptr() as *mut libc::c_void,
+            1,
+            libc::MSG_PEEK,
+        )
+    };
+    match ret {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        _ => Ok(Some(byte[0] == 0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn status_request(version: u32) -> Vec<u8> {
+        format!(r#"{{"version": {version}, "id": 7, "action": {{"Status": null}}}}"#).into_bytes()
+    }
+
+    #[test]
+    fn round_trips_frames() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, b"first").unwrap();
+        write_frame(&mut buffer, b"").unwrap();
+        assert_eq!(&buffer[..4], &5u32.to_be_bytes());
+        let mut reader = Cursor::new(buffer);
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), b"first");
+        assert_eq!(read_frame(&mut reader).unwrap().unwrap(), b"");
+        assert!(read_frame(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_oversized_and_truncated_frames() {
+        let header = (MAX_FRAME_SIZE as u32 + 1).to_be_bytes();
+        let error = read_frame(&mut Cursor::new(header)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        let mut truncated = 10u32.to_be_bytes().to_vec();
+        truncated.extend(b"short");
+        let error = read_frame(&mut Cursor::new(truncated)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn parses_requests() {
+        let request = parse_request(&status_request(PROTOCOL_VERSION)).unwrap();
+        assert_eq!(request.id, 7);
+        assert!(request.action == Action::Status(None));
+        assert!(request.format == Format::Text);
+        assert_eq!(
+            parse_request(&status_request(2)).err().unwrap(),
+            (7, "Unsupported protocol version 2, expected 1".to_string())
+        );
+        assert_eq!(parse_request(b"{").err().unwrap().0, 0);
+        let (id, error) = parse_request(br#"{"version": 1, "id": 3, "action": "Dance"}"#)
+            .err()
+            .unwrap();
+        assert_eq!(id, 3);
+        assert!(error.starts_with("Unknown action"), "{error}");
+    }
+
+    #[test]
+    fn detects_framed_connections_by_the_first_byte() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        write_frame(&mut client, &status_request(PROTOCOL_VERSION)).unwrap();
+        assert_eq!(is_framed(&server).unwrap(), Some(true));
+        assert!(read_frame(&mut &server).unwrap().is_some());
+
+        let (mut client, server) = UnixStream::pair().unwrap();
+        client.write_all(br#"{"Status": null}"#).unwrap();
+        assert_eq!(is_framed(&server).unwrap(), Some(false));
+
+        let (client, server) = UnixStream::pair().unwrap();
+        drop(client);
+        assert_eq!(is_framed(&server).unwrap(), None);
+    }
+}
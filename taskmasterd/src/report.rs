@@ -0,0 +1,449 @@
+use crate::cgroup::Usage;
+use crate::configuration::Configuration;
+use crate::configuration::State::{BACKOFF, FATAL, RUNNING, UNHEALTHY};
+use crate::exit::ExitRecord;
+use crate::identity::user_name;
+use crate::task::Task;
+use crate::utils::get_effective_uid;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, SystemTime};
+
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    TaskNotFound,
+    ProcessNotFound,
+    InvalidState,
+    StartFailed,
+    StopFailed,
+    SignalFailed,
+    LogNotConfigured,
+    InvalidConfiguration,
+    HttpLogging,
+    PermissionDenied,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProcessStatus {
+    pub index: usize,
+    pub state: &'static str,
+    pub description: String,
+    pub pid: Option<u32>,
+    pub uid: Option<u32>,
+    pub user: Option<String>,
+    pub uptime: Option<u64>,
+    pub retry_in: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub restarts_left: u32,
+    pub reason: Option<String>,
+    pub waiting_for: Vec<String>,
+    pub stopping_after: Vec<String>,
+    pub exits: Vec<ExitRecord>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProcessConfiguration {
+    pub index: usize,
+    pub cmd: String,
+    pub working_dir: Option<String>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+    pub env: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GroupStatus {
+    pub name: String,
+    pub usage: Option<Usage>,
+    pub processes: Vec<ProcessStatus>,
+    #[serde(skip)]
+    pub shows_exits: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProcessResult {
+    pub name: String,
+    pub index: usize,
+    pub error: Option<ErrorKind>,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Added,
+    Updated,
+    Removed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ConfigurationChange {
+    pub name: String,
+    pub change: Change,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Report {
+    Status {
+        groups: Vec<GroupStatus>,
+    },
+    Processes {
+        results: Vec<ProcessResult>,
+    },
+    Configuration {
+        name: String,
+        source: Option<String>,
+        configuration: Box<Configuration>,
+        processes: Vec<ProcessConfiguration>,
+    },
+    Update {
+        changes: Vec<ConfigurationChange>,
+    },
+    Info {
+        message: String,
+    },
+    Error {
+        kind: ErrorKind,
+        message: String,
+    },
+}
+
+impl ProcessStatus {
+    pub fn new(index: usize, task: &Task) -> ProcessStatus {
+        let pid = task.child.as_ref().map(|child| child.id());
+        let uid = match task.state {
+            RUNNING(_) | UNHEALTHY(_) => pid.and_then(get_effective_uid),
+            _ => None,
+        };
+        ProcessStatus {
+            index,
+            state: task.state.keyword(),
+            description: task.to_string(),
+            pid,
+            uid,
+            user: uid.and_then(user_name),
+            uptime: match task.state {
+                RUNNING(started_at) | UNHEALTHY(started_at) => Some(
+                    SystemTime::now()
+                        .duration_since(started_at)
+                        .unwrap_or(Duration::from_secs(0))
+                        .as_secs(),
+                ),
+                _ => None,
+            },
+            retry_in: match task.state {
+                BACKOFF(until) => Some(
+                    until
+                        .duration_since(SystemTime::now())
+                        .unwrap_or(Duration::from_secs(0))
+                        .as_secs(),
+                ),
+                _ => None,
+            },
+            exit_code: task.exit_code,
+            restarts_left: task.restarts_left,
+            reason: match &task.state {
+                FATAL(reason) => Some(reason.clone()),
+                UNHEALTHY(_) => task.health_error(),
+                _ => None,
+            },
+            waiting_for: task.waiting_for.clone(),
+            stopping_after: task.stopping_after.clone(),
+            exits: task.exit_records.iter().cloned().collect(),
+        }
+    }
+}
+
+impl ProcessConfiguration {
+    pub fn new(index: usize, configuration: &Configuration) -> ProcessConfiguration {
+        ProcessConfiguration {
+            index,
+            cmd: configuration.cmd.clone(),
+            working_dir: configuration.working_dir.clone(),
+            stdout: configuration.stdout.clone(),
+            stderr: configuration.stderr.clone(),
+            env: configuration.env.clone(),
+        }
+    }
+}
+
+impl ProcessResult {
+    pub fn ok(name: &str, index: usize, message: String) -> ProcessResult {
+        ProcessResult {
+            name: name.to_string(),
+            index,
+            error: None,
+            message,
+        }
+    }
+
+    pub fn error(name: &str, index: usize, kind: ErrorKind, message: String) -> ProcessResult {
+        ProcessResult {
+            name: name.to_string(),
+            index,
+            error: Some(kind),
+            message,
+        }
+    }
+}
+
+impl Report {
+    pub fn error(kind: ErrorKind, message: String) -> Report {
+        Report::Error { kind, message }
+    }
+
+    pub fn task_not_found(name: &str) -> Report {
+        Report::error(
+            ErrorKind::TaskNotFound,
+            format!("Can't find \"{name}\" task"),
+        )
+    }
+
+    pub fn info(message: String) -> Report {
+        Report::Info { message }
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self, Report::Error { .. })
+    }
+}
+
+impl Display for ProcessResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}[{}]: {}", self.name, self.index, self.message)
+    }
+}
+
+impl GroupStatus {
+    fn write_exits(
+        &self,
+        f: &mut Formatter<'_>,
+        process: &ProcessStatus,
+        indent: &str,
+    ) -> std::fmt::Result {
+        if !self.shows_exits {
+            return Ok(());
+        }
+        for record in process.exits.iter().rev() {
+            let expected = if record.expected { "" } else { ", unexpected" };
+            write!(f, "\n{indent}exited {}: {record}{expected}", record.at())?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for GroupStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let usage = match &self.usage {
+            Some(usage) => format!(" [{usage}]"),
+            None => String::new(),
+        };
+        if self.processes.len() == 1 {
+            write!(f, "{}: {}{usage}", self.name, self.processes[0].description)?;
+            self.write_exits(f, &self.processes[0], "\t")
+        } else {
+            write!(f, "{}:{usage}\t\t", self.name)?;
+            for process in &self.processes {
+                write!(f, "\n\t{}. {}", process.index, process.description)?;
+                self.write_exits(f, process, "\t\t")?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl Display for ConfigurationChange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.change {
+            Change::Added => writeln!(f, "{}: added", self.name),
+            Change::Updated => writeln!(f, "{}: updated", self.name),
+            Change::Removed => writeln!(f, "{} has been deleted", self.name),
+        }
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Report::Status { groups } if groups.is_empty() => write!(f, "No task found."),
+            Report::Status { groups } => write!(
+                f,
+                "{}",
+                groups
+                    .iter()
+                    .map(|group| group.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            ),
+            Report::Processes { results } => {
+                results.iter().try_for_each(|result| write!(f, "{result}"))
+            }
+            Report::Configuration {
+                name,
+                source,
+                configuration,
+                processes,
+            } => {
+                if let Some(source) = source {
+                    writeln!(f, "{name} is defined in {source}")?;
+                }
+                write!(
+                    f,
+                    "{name}: {}",
+                    serde_json::to_string_pretty(configuration).expect("Serialization failed")
+                )?;
+                let is_expanded = processes.iter().any(|process| {
+                    process.cmd != configuration.cmd
+                        || process.working_dir != configuration.working_dir
+                        || process.stdout != configuration.stdout
+                        || process.stderr != configuration.stderr
+                        || process.env != configuration.env
+                });
+                if is_expanded {
+                    for process in processes {
+                        write!(
+                            f,
+                            "\n{name}[{}]: {}",
+                            process.index,
+                            serde_json::to_string(process).expect("Serialization failed")
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+            Report::Update { changes } if changes.is_empty() => write!(f, "Already up to date."),
+            Report::Update { changes } => {
+                changes.iter().try_for_each(|change| write!(f, "{change}"))
+            }
+            Report::Info { message } => write!(f, "{message}"),
+            Report::Error { message, .. } => write!(f, "{message}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn process(index: usize, description: &str) -> ProcessStatus {
+        ProcessStatus {
+            index,
+            state: "RUNNING",
+            description: description.to_string(),
+            pid: Some(42),
+            uid: None,
+            user: None,
+            uptime: Some(3),
+            retry_in: None,
+            exit_code: None,
+            restarts_left: 3,
+            reason: None,
+            waiting_for: Vec::new(),
+            stopping_after: Vec::new(),
+            exits: Vec::new(),
+        }
+    }
+
+    fn group(name: &str, processes: Vec<ProcessStatus>) -> GroupStatus {
+        GroupStatus {
+            name: name.to_string(),
+            usage: None,
+            processes,
+            shows_exits: false,
+        }
+    }
+
+    #[test]
+    fn serializes_tagged_reports() {
+        let report = Report::error(ErrorKind::TaskNotFound, "missing".to_string());
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({"type": "error", "kind": "task_not_found", "message": "missing"})
+        );
+        let report = Report::Processes {
+            results: vec![ProcessResult::error(
+                "web",
+                1,
+                ErrorKind::InvalidState,
+                "already stopped".to_string(),
+            )],
+        };
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({"type": "processes", "results": [
+                {"name": "web", "index": 1, "error": "invalid_state", "message": "already stopped"}
+            ]})
+        );
+        let report = Report::Update {
+            changes: vec![ConfigurationChange {
+                name: "web".to_string(),
+                change: Change::Removed,
+            }],
+        };
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({"type": "update", "changes": [{"name": "web", "change": "removed"}]})
+        );
+        let report = Report::Status {
+            groups: vec![group("web", vec![process(0, "running")])],
+        };
+        let value = serde_json::to_value(&report).unwrap();
+        assert_eq!(value["type"], "status");
+        assert_eq!(value["groups"][0]["processes"][0]["state"], "RUNNING");
+        assert_eq!(value["groups"][0]["processes"][0]["pid"], 42);
+        assert!(value["groups"][0].get("shows_exits").is_none());
+    }
+
+    #[test]
+    fn displays_reports_as_text() {
+        let report = Report::Status {
+            groups: vec![
+                group("db", vec![process(0, "running")]),
+                group("web", vec![process(0, "running"), process(1, "stopped")]),
+            ],
+        };
+        assert_eq!(
+            report.to_string(),
+            "db: running\nweb:\t\t\n\t0. running\n\t1. stopped"
+        );
+        assert_eq!(
+            Report::Status { groups: Vec::new() }.to_string(),
+            "No task found."
+        );
+        let report = Report::Processes {
+            results: vec![
+                ProcessResult::ok("web", 0, "started".to_string()),
+                ProcessResult::ok("web", 1, "started".to_string()),
+            ],
+        };
+        assert_eq!(report.to_string(), "web[0]: started\nweb[1]: started\n");
+        let report = Report::Update {
+            changes: vec![
+                ConfigurationChange {
+                    name: "db".to_string(),
+                    change: Change::Added,
+                },
+                ConfigurationChange {
+                    name: "web".to_string(),
+                    change: Change::Removed,
+                },
+            ],
+        };
+        assert_eq!(report.to_string(), "db: added\nweb has been deleted\n");
+        assert_eq!(
+            Report::Update {
+                changes: Vec::new()
+            }
+            .to_string(),
+            "Already up to date."
+        );
+        assert_eq!(
+            Report::task_not_found("x").to_string(),
+            "Can't find \"x\" task"
+        );
+    }
+}
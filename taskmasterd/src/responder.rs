@@ -1,79 +1,199 @@
+use crate::access::Peer;
 use crate::action::Action;
+use crate::log_file;
 use crate::logger::{LogLine, Logger};
 use crate::monitor::Monitor;
+use crate::output::OutputBuffer;
+use crate::protocol::{self, Format, Payload, Response, Status, PROTOCOL_VERSION};
+use crate::remove_and_exit;
+use crate::report::Report;
 use crate::responder::Respond::Message;
-use crate::{remove_and_exit, UNIX_DOMAIN_SOCKET_PATH};
-use std::borrow::Cow;
+use crate::settings::{settings, LogLevel};
 use std::collections::VecDeque;
-use std::io::{Read, Seek, Write};
-use std::os::unix::fs::PermissionsExt;
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
 use std::time::Duration;
-use std::{fs, thread};
+
+const REJECT_TIMEOUT: Duration = Duration::from_millis(100);
 
 pub enum Respond {
     Message(String),
+    Error(String),
+    Report(Report),
     MaintailStream(Option<usize>),
-    Tail(String, Option<usize>, bool),
+    Tail(String, Option<usize>),
+    TailStream(Arc<Mutex<OutputBuffer>>, Option<usize>),
+    Shutdown,
+}
+
+pub struct Reply {
+    stream: UnixStream,
+    request_id: Option<u64>,
+    format: Format,
+    is_stream: bool,
+    _connection: Option<Arc<Connection>>,
+}
+
+pub struct Limits {
+    pub max_connections: usize,
+    pub request_timeout: Duration,
+}
+
+struct Connection {
+    connections: Arc<AtomicUsize>,
+}
+
+impl Connection {
+    fn acquire(connections: &Arc<AtomicUsize>, max_connections: usize) -> Option<Connection> {
+        connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < max_connections).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| Connection {
+                connections: connections.clone(),
+            })
+    }
 }
 
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Clone)]
 pub struct Responder {
     logger: Arc<Mutex<Logger>>,
-    monitor: Monitor,
+    monitor: Arc<Mutex<Monitor>>,
 }
 
 impl Responder {
     fn bind_listener(&self) -> UnixListener {
         let mut logger = self.logger.lock().unwrap();
-        match UnixListener::bind(UNIX_DOMAIN_SOCKET_PATH) {
+        let socket = &settings().socket;
+        match UnixListener::bind(socket) {
             Ok(stream) => {
-                if let Err(_) =
-                    fs::set_permissions(UNIX_DOMAIN_SOCKET_PATH, fs::Permissions::from_mode(0o666))
-                {
-                    logger.log_err(format!(
-                        "Can't change permissions of \"{UNIX_DOMAIN_SOCKET_PATH}\""
-                    ));
+                if let Err(err_msg) = settings().secure_socket() {
+                    logger.log(LogLevel::Error, err_msg);
                     remove_and_exit(2);
                 }
-                logger.log(format!(
-                    "Socket was successfully created: {UNIX_DOMAIN_SOCKET_PATH}"
-                ));
+                logger.log(
+                    LogLevel::Info,
+                    format!("Socket was successfully created: {socket}"),
+                );
                 stream
             }
-            Err(_) => {
-                logger.log_err(format!("Can't bind socket \"{UNIX_DOMAIN_SOCKET_PATH}\""));
+            Err(e) => {
+                logger.log(
+                    LogLevel::Error,
+                    format!("Can't bind socket \"{socket}\": {e}"),
+                );
                 remove_and_exit(2);
             }
         }
     }
 
-    fn write_message(
-        mut stream: &UnixStream,
-        message: &str,
-        logger: &mut MutexGuard<Logger>,
-    ) -> bool {
-        if let Err(e) = stream.write(message.as_bytes()) {
-            logger.resp_log(format!(
-                "Can't answer to the client with message: \"{message}\": {e}"
-            ));
+    fn send(reply: &Reply, status: Status, payload: Payload, more: bool) -> std::io::Result<()> {
+        let mut stream = &reply.stream;
+        match reply.request_id {
+            None => match payload {
+                Payload::Message(message) => stream.write_all(message.as_bytes())?,
+                Payload::Result(report) => stream.write_all(report.to_string().as_bytes())?,
+            },
+            Some(id) => {
+                let response = Response {
+                    version: PROTOCOL_VERSION,
+                    id,
+                    status,
+                    payload,
+                    more,
+                };
+                let frame = serde_json::to_vec(&response)?;
+                protocol::write_frame(&mut stream, &frame)?;
+            }
+        }
+        stream.flush()
+    }
+
+    fn write_message(reply: &Reply, message: &str, logger: &mut MutexGuard<Logger>) -> bool {
+        if let Err(e) = Responder::send(
+            reply,
+            Status::Ok,
+            Payload::Message(message),
+            reply.is_stream,
+        ) {
+            logger.resp_log(
+                LogLevel::Error,
+                format!("Can't answer to the client with message: \"{message}\": {e}"),
+            );
+            return false;
+        }
+        logger.resp_log(
+            LogLevel::Debug,
+            format!("Sending the answer: \"{message}\""),
+        );
+        true
+    }
+
+    fn write_error(reply: &Reply, message: &str, logger: &mut MutexGuard<Logger>) -> bool {
+        if let Err(e) = Responder::send(reply, Status::Error, Payload::Message(message), false) {
+            logger.resp_log(
+                LogLevel::Error,
+                format!("Can't answer to the client with error: \"{message}\": {e}"),
+            );
             return false;
         }
-        logger.resp_log(format!("Sending the answer: \"{message}\""));
-        if let Err(e) = stream.flush() {
-            logger.resp_log(format!("Can't flush the stdout: {e}"));
+        logger.resp_log(LogLevel::Debug, format!("Sending the error: \"{message}\""));
+        true
+    }
+
+    fn write_report(reply: &Reply, report: &Report, logger: &mut MutexGuard<Logger>) -> bool {
+        let status = if report.is_error() {
+            Status::Error
+        } else {
+            Status::Ok
+        };
+        let result = match reply.format {
+            Format::Text => {
+                Responder::send(reply, status, Payload::Message(&report.to_string()), false)
+            }
+            Format::Json => Responder::send(reply, status, Payload::Result(report), false),
+        };
+        if let Err(e) = result {
+            logger.resp_log(
+                LogLevel::Error,
+                format!("Can't answer to the client with report: \"{report}\": {e}"),
+            );
             return false;
         }
+        logger.resp_log(LogLevel::Debug, format!("Sending the answer: \"{report}\""));
         true
     }
 
-    fn handle_response(&mut self, stream: UnixStream, respond: Respond) {
+    fn handle_response(&self, mut reply: Reply, respond: Respond) -> bool {
         match respond {
+            Respond::Report(report) => {
+                let mut logger = self.logger.lock().unwrap();
+                Responder::write_report(&reply, &report, &mut logger);
+                true
+            }
             Message(message) => {
                 let mut logger = self.logger.lock().unwrap();
-                Responder::write_message(&stream, &message, &mut logger);
+                Responder::write_message(&reply, &message, &mut logger);
+                true
+            }
+            Respond::Error(message) => {
+                let mut logger = self.logger.lock().unwrap();
+                Responder::write_error(&reply, &message, &mut logger);
+                true
             }
             Respond::MaintailStream(num_lines) => {
+                reply.is_stream = true;
                 let logger_clone = self.logger.clone();
                 thread::spawn(move || {
                     let mut history_buffer: VecDeque<LogLine> = {
@@ -99,7 +219,7 @@ impl Responder {
                         while !history_buffer.is_empty() {
                             if let Some((idx, message)) = history_buffer.pop_front() {
                                 last_logged_idx = idx;
-                                if !Responder::write_message(&stream, &message, &mut logger) {
+                                if !Responder::write_message(&reply, &message, &mut logger) {
                                     eprintln!("Exiting maintail -f: can't write or flush");
                                     break 'outer;
                                 }
@@ -125,151 +245,251 @@ impl Responder {
                         thread::sleep(Duration::from_millis(100));
                     }
                 });
+                false
             }
-            Respond::Tail(filename, num_lines, is_stream) => match fs::File::open(&filename) {
-                Ok(mut file) => {
-                    let mut buffer = String::new();
-                    match file.read_to_string(&mut buffer) {
-                        Ok(_) => {
-                            let logger_clone = self.logger.clone();
-                            let mut logger = self.logger.lock().unwrap();
-                            match num_lines {
-                                None => {
-                                    Responder::write_message(&stream, &buffer, &mut logger);
-                                }
-                                Some(num) => {
-                                    let ends_with_newline = buffer.ends_with("\n");
-                                    let mut lines: Vec<String> =
-                                        buffer.lines().rev().take(num).map(String::from).collect();
-                                    lines = lines.iter().cloned().rev().collect();
-                                    let mut output = lines.join("\n");
-                                    if ends_with_newline {
-                                        output += "\n";
-                                    }
-                                    Responder::write_message(&stream, &output, &mut logger);
-                                }
-                            }
-                            if is_stream {
-                                let mut last_size = buffer.len() as u64;
-                                thread::spawn(move || loop {
-                                    thread::sleep(Duration::from_millis(100));
-                                    let mut logger = logger_clone.lock().unwrap();
-                                    let new_size = match fs::metadata(&filename) {
-                                        Ok(metadata) => metadata.len(),
-                                        Err(err) => {
-                                            Responder::write_message(
-                                                &stream,
-                                                &format!("\nCan't access len of {filename}: {err}"),
-                                                &mut logger,
-                                            );
-                                            return;
-                                        }
-                                    };
-                                    if new_size < last_size {
-                                        if !Responder::write_message(
-                                            &stream,
-                                            &format!("\n\ntail: {filename}: file truncated\n\n"),
-                                            &mut logger,
-                                        ) {
-                                            return;
-                                        }
-                                        if let Err(err) = file.seek(std::io::SeekFrom::Start(0)) {
-                                            Responder::write_message(
-                                                &stream,
-                                                &format!(
-                                                    "\nFailed to rewind file {filename}: {err}"
-                                                ),
-                                                &mut logger,
-                                            );
-                                            return;
-                                        }
-                                    } else if new_size > last_size {
-                                        let mut new_content = String::new();
-                                        if let Err(err) = file.read_to_string(&mut new_content) {
-                                            Responder::write_message(
-                                                &stream,
-                                                &format!("\nFailed to read file {filename}: {err}"),
-                                                &mut logger,
-                                            );
-                                            return;
-                                        }
-                                        if !Responder::write_message(
-                                            &stream,
-                                            &new_content,
-                                            &mut logger,
-                                        ) {
-                                            return;
-                                        }
-                                    }
-                                    last_size = new_size;
-                                });
-                            }
-                        }
-                        Err(err) => {
-                            Responder::write_message(
-                                &stream,
-                                &format!("Failed to read file {filename}: {err}"),
-                                &mut self.logger.lock().unwrap(),
-                            );
+            Respond::Tail(filename, num_lines) => {
+                let tail = log_file::read_tail(&filename, num_lines);
+                let mut logger = self.logger.lock().unwrap();
+                match tail {
+                    Ok(output) => Responder::write_message(&reply, &output, &mut logger),
+                    Err(err) => Responder::write_error(&reply, &err, &mut logger),
+                };
+                true
+            }
+            Respond::TailStream(buffer, num_lines) => {
+                reply.is_stream = true;
+                let logger_clone = self.logger.clone();
+                thread::spawn(move || {
+                    let (output, mut last_idx) = buffer.lock().unwrap().tail(num_lines);
+                    if !Responder::write_message(&reply, &output, &mut logger_clone.lock().unwrap())
+                    {
+                        return;
+                    }
+                    loop {
+                        thread::sleep(Duration::from_millis(100));
+                        let (output, idx) = buffer.lock().unwrap().since(last_idx);
+                        last_idx = idx;
+                        if !output.is_empty()
+                            && !Responder::write_message(
+                                &reply,
+                                &output,
+                                &mut logger_clone.lock().unwrap(),
+                            )
+                        {
+                            return;
                         }
                     }
-                }
-                Err(err) => {
-                    Responder::write_message(
-                        &stream,
-                        &format!("Failed to open file {filename}: {err}"),
-                        &mut self.logger.lock().unwrap(),
-                    );
-                }
-            },
+                });
+                false
+            }
+            Respond::Shutdown => {
+                thread::spawn(move || {
+                    let _reply = reply;
+                    loop {
+                        thread::park();
+                    }
+                });
+                false
+            }
         }
     }
 
-    fn handle_message(&mut self, stream: UnixStream, received_data: Cow<str>) {
+    fn handle_legacy_connection(&self, mut stream: UnixStream, connection: Connection, peer: Peer) {
+        let mut buffer = [0; 1024];
+        let received_data = match stream.read(&mut buffer) {
+            Ok(0) => return,
+            Ok(bytes_read) => String::from_utf8_lossy(&buffer[..bytes_read]).to_string(),
+            Err(e) => {
+                self.log_stream_error(e);
+                return;
+            }
+        };
         {
             let mut logger = self.logger.lock().unwrap();
-            logger.resp_log(format!("Received via socket: {received_data}"));
+            logger.resp_log(
+                LogLevel::Debug,
+                format!("Received via socket: {received_data}"),
+            );
         }
-        match serde_json::from_str::<Action>(received_data.to_string().as_str()) {
+        let reply = Reply {
+            stream,
+            request_id: None,
+            format: Format::Text,
+            is_stream: false,
+            _connection: Some(Arc::new(connection)),
+        };
+        match serde_json::from_str::<Action>(&received_data) {
             Ok(action) => {
-                let answer = self.monitor.handle_action(action);
-                self.handle_response(stream, answer);
+                let answer = self.monitor.lock().unwrap().handle_action(action, &peer);
+                self.handle_response(reply, answer);
             }
             Err(error) => {
                 {
                     let mut logger = self.logger.lock().unwrap();
-                    logger.resp_log(format!("Unknown action: {received_data}: {error}"));
+                    logger.resp_log(
+                        LogLevel::Debug,
+                        format!("Unknown action: {received_data}: {error}"),
+                    );
+                }
+                self.handle_response(reply, Message("Unknown action".to_string()));
+            }
+        }
+    }
+
+    fn handle_framed_connection(&self, stream: UnixStream, connection: Connection, peer: Peer) {
+        let connection = Arc::new(connection);
+        loop {
+            let frame = match protocol::read_frame(&mut &stream) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return,
+                Err(e) => {
+                    self.log_stream_error(e);
+                    return;
+                }
+            };
+            let reply_stream = match stream.try_clone() {
+                Ok(reply_stream) => reply_stream,
+                Err(e) => {
+                    self.log_stream_error(e);
+                    return;
+                }
+            };
+            {
+                let mut logger = self.logger.lock().unwrap();
+                logger.resp_log(
+                    LogLevel::Debug,
+                    format!("Received via socket: {}", String::from_utf8_lossy(&frame)),
+                );
+            }
+            match protocol::parse_request(&frame) {
+                Ok(request) => {
+                    let reply = Reply {
+                        stream: reply_stream,
+                        request_id: Some(request.id),
+                        format: request.format,
+                        is_stream: false,
+                        _connection: Some(connection.clone()),
+                    };
+                    let answer = self
+                        .monitor
+                        .lock()
+                        .unwrap()
+                        .handle_action(request.action, &peer);
+                    if !self.handle_response(reply, answer) {
+                        return;
+                    }
                 }
-                self.handle_response(stream, Message("Unknown action".to_string()));
+                Err((id, error)) => {
+                    let reply = Reply {
+                        stream: reply_stream,
+                        request_id: Some(id),
+                        format: Format::Text,
+                        is_stream: false,
+                        _connection: Some(connection.clone()),
+                    };
+                    self.handle_response(reply, Respond::Error(error));
+                }
+            }
+        }
+    }
+
+    fn handle_connection(&self, stream: UnixStream, connection: Connection, limits: &Limits) {
+        if let Err(e) = stream
+            .set_read_timeout(Some(limits.request_timeout))
+            .and_then(|_| stream.set_write_timeout(Some(limits.request_timeout)))
+        {
+            let mut logger = self.logger.lock().unwrap();
+            logger.resp_log(LogLevel::Error, format!("Stream: {e}"));
+            return;
+        }
+        let peer = match Peer::from_stream(&stream) {
+            Ok(peer) => peer,
+            Err(e) => {
+                let mut logger = self.logger.lock().unwrap();
+                logger.resp_log(
+                    LogLevel::Error,
+                    format!("Can't get the peer credentials: {e}"),
+                );
+                return;
+            }
+        };
+        match protocol::is_framed(&stream) {
+            Ok(None) => {}
+            Ok(Some(true)) => self.handle_framed_connection(stream, connection, peer),
+            Ok(Some(false)) => self.handle_legacy_connection(stream, connection, peer),
+            Err(e) => {
+                self.log_stream_error(e);
             }
         }
     }
 
-    pub fn listen(monitor: Monitor, logger: Arc<Mutex<Logger>>) {
-        let mut responder = Responder { logger, monitor };
+    fn reject_connection(&self, stream: UnixStream, limits: &Limits) {
+        let message = format!(
+            "Too many connections (limit is {}), try again later",
+            limits.max_connections
+        );
+        let mut logger = self.logger.lock().unwrap();
+        logger.resp_log(LogLevel::Debug, format!("Connection rejected: {message}"));
+        let _ = stream.set_read_timeout(Some(REJECT_TIMEOUT));
+        let _ = stream.set_write_timeout(Some(REJECT_TIMEOUT));
+        let is_framed = matches!(protocol::is_framed(&stream), Ok(Some(true)));
+        let reply = Reply {
+            stream,
+            request_id: is_framed.then_some(0),
+            format: Format::Text,
+            is_stream: false,
+            _connection: None,
+        };
+        Responder::write_error(&reply, &message, &mut logger);
+        Responder::discard_pending(&reply.stream);
+    }
+
+    fn discard_pending(stream: &UnixStream) {
+        let mut buffer = [0u8; 1024];
+        while unsafe {
+            libc::recv(
+                stream.as_raw_fd(),
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+                libc::MSG_DONTWAIT,
+            )
+        } > 0
+        {}
+    }
+
+    fn log_stream_error(&self, error: std::io::Error) {
+        let mut logger = self.logger.lock().unwrap();
+        match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => logger.resp_log(
+                LogLevel::Info,
+                "Stream: request timed out, closing the connection".to_string(),
+            ),
+            _ => logger.resp_log(LogLevel::Error, format!("Stream: {error}")),
+        };
+    }
+
+    pub fn listen(monitor: Monitor, logger: Arc<Mutex<Logger>>, limits: Limits) {
+        let responder = Responder {
+            logger,
+            monitor: Arc::new(Mutex::new(monitor)),
+        };
+        let limits = Arc::new(limits);
+        let connections = Arc::new(AtomicUsize::new(0));
         for stream in responder.bind_listener().incoming() {
             match stream {
-                Ok(mut stream) => {
-                    let mut buffer = [0; 1024];
-                    match stream.read(&mut buffer) {
-                        Ok(bytes_read) => {
-                            if bytes_read == 0 {
-                                continue;
-                            }
-                            responder.handle_message(
-                                stream,
-                                String::from_utf8_lossy(&buffer[..bytes_read]),
-                            );
-                        }
-                        Err(e) => {
-                            let mut logger = responder.logger.lock().unwrap();
-                            logger.resp_log(format!("Stream: {e}"));
-                        }
+                Ok(stream) => match Connection::acquire(&connections, limits.max_connections) {
+                    Some(connection) => {
+                        let responder = responder.clone();
+                        let limits = limits.clone();
+                        thread::spawn(move || {
+                            responder.handle_connection(stream, connection, &limits)
+                        });
                     }
-                }
+                    None => responder.reject_connection(stream, &limits),
+                },
                 Err(e) => {
-                    let logger = responder.logger.lock().unwrap();
-                    logger.log_err(format!("Can't accept a connection: {e}"));
+                    let mut logger = responder.logger.lock().unwrap();
+                    logger.log(LogLevel::Error, format!("Can't accept a connection: {e}"));
                 }
             }
         }
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{Display, Formatter};
+use std::io;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    Nofile,
+    Nproc,
+    As,
+    Core,
+    Cpu,
+    Fsize,
+    Stack,
+    Memlock,
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+pub enum RlimitValue {
+    Limited(u64),
+    Unlimited,
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize, Clone, Copy)]
+pub struct Rlimit {
+    pub soft: RlimitValue,
+    pub hard: RlimitValue,
+}
+
+impl Display for Resource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resource::Nofile => write!(f, "nofile"),
+            Resource::Nproc => write!(f, "nproc"),
+            Resource::As => write!(f, "as"),
+            Resource::Core => write!(f, "core"),
+            Resource::Cpu => write!(f, "cpu"),
+            Resource::Fsize => write!(f, "fsize"),
+            Resource::Stack => write!(f, "stack"),
+            Resource::Memlock => write!(f, "memlock"),
+        }
+    }
+}
+
+impl Display for RlimitValue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RlimitValue::Limited(value) => write!(f, "{value}"),
+            RlimitValue::Unlimited => write!(f, "unlimited"),
+        }
+    }
+}
+
+impl From<RlimitValue> for libc::rlim_t {
+    fn from(value: RlimitValue) -> Self {
+        match value {
+            RlimitValue::Limited(value) => value as libc::rlim_t,
+            RlimitValue::Unlimited => libc::RLIM_INFINITY,
+        }
+    }
+}
+
+impl Serialize for RlimitValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            RlimitValue::Limited(value) => serializer.serialize_u64(*value),
+            RlimitValue::Unlimited => serializer.serialize_str("unlimited"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for RlimitValue {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Number(u64),
+            Keyword(String),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Number(value) => Ok(RlimitValue::Limited(value)),
+            Value::Keyword(keyword) if keyword.trim() == "unlimited" => Ok(RlimitValue::Unlimited),
+            Value::Keyword(keyword) => Err(serde::de::Error::custom(format!(
+                "\"{keyword}\" is not a valid limit, expected a number or \"unlimited\""
+            ))),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Rlimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Pair {
+            soft: RlimitValue,
+            hard: Option<RlimitValue>,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Both(RlimitValue),
+            Pair(Pair),
+        }
+
+        match Value::deserialize(deserializer)? {
+            Value::Both(value) => Ok(Rlimit {
+                soft: value,
+                hard: value,
+            }),
+            Value::Pair(Pair { soft, hard }) => Ok(Rlimit {
+                soft,
+                hard: hard.unwrap_or(soft),
+            }),
+        }
+    }
+}
+
+impl Rlimit {
+    pub fn validate(&self, resource: Resource) -> Result<(), String> {
+        if self.soft > self.hard {
+            return Err(format!(
+                "rlimits: {resource}: soft limit {} is bigger than hard limit {}",
+                self.soft, self.hard
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, resource: Resource) -> io::Result<()> {
+        let limit = libc::rlimit {
+            rlim_cur: self.soft.into(),
+            rlim_max: self.hard.into(),
+        };
+        let resource = match resource {
+            Resource::Nofile => libc::RLIMIT_NOFILE,
+            Resource::Nproc => libc::RLIMIT_NPROC,
+            Resource::As => libc::RLIMIT_AS,
+            Resource::Core => libc::RLIMIT_CORE,
+            Resource::Cpu => libc::RLIMIT_CPU,
+            Resource::Fsize => libc::RLIMIT_FSIZE,
+            Resource::Stack => libc::RLIMIT_STACK,
+            Resource::Memlock => libc::RLIMIT_MEMLOCK,
+        };
+        if unsafe { libc::setrlimit(resource, &limit) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn parse(yaml: &str) -> Result<BTreeMap<Resource, Rlimit>, serde_yaml::Error> {
+        serde_yaml::from_str(yaml)
+    }
+
+    #[test]
+    fn parses_limits() {
+        let limits = parse(
+            "nofile: 1024\ncore: unlimited\nstack: {soft: 8192, hard: unlimited}\ncpu: {soft: 10}",
+        )
+        .unwrap();
+        assert_eq!(
+            limits[&Resource::Nofile],
+            Rlimit {
+                soft: RlimitValue::Limited(1024),
+                hard: RlimitValue::Limited(1024),
+            }
+        );
+        assert_eq!(limits[&Resource::Core].hard, RlimitValue::Unlimited);
+        assert_eq!(limits[&Resource::Stack].soft, RlimitValue::Limited(8192));
+        assert_eq!(limits[&Resource::Stack].hard, RlimitValue::Unlimited);
+        assert_eq!(limits[&Resource::Cpu].hard, RlimitValue::Limited(10));
+    }
+
+    #[test]
+    fn rejects_invalid_limits() {
+        assert!(parse("nofile: lots").is_err());
+        assert!(parse("nofile: {soft: 1, maximum: 2}").is_err());
+        assert!(parse("files: 1").is_err());
+        let inverted = Rlimit {
+            soft: RlimitValue::Unlimited,
+            hard: RlimitValue::Limited(64),
+        };
+        assert!(inverted.validate(Resource::Nofile).is_err());
+        assert_eq!(
+            Rlimit {
+                soft: RlimitValue::Limited(64),
+                hard: RlimitValue::Unlimited,
+            }
+            .validate(Resource::Nofile),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,263 @@
+use crate::diagnostic::Diagnostics;
+use crate::identity::{lookup_group, lookup_user};
+use libc::{gid_t, uid_t};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::sync::OnceLock;
+
+pub const DAEMON_SECTION: &str = "daemon";
+const DEFAULT_SOCKET_PATH: &str = "/tmp/taskmaster.sock";
+const DEFAULT_PID_FILE_PATH: &str = "/tmp/taskmasterd.pid";
+const DEFAULT_LOG_FILE_PATH: &str = "/tmp/taskmasterd.log";
+const DEFAULT_SOCKET_MODE: u32 = 0o666;
+const DEFAULT_CHILD_LOG_DIR: &str = "/var/log/taskmaster";
+const USER_CHILD_LOG_DIR: &str = "/tmp/taskmaster";
+const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup/taskmaster";
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRetention {
+    Remove,
+    Truncate,
+    Append,
+}
+
+#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Info,
+    Debug,
+}
+
+#[derive(Debug, Eq, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub socket: String,
+    pub socket_owner: Option<String>,
+    pub socket_group: Option<String>,
+    #[serde(deserialize_with = "deserialize_mode")]
+    pub socket_mode: u32,
+    pub pidfile: String,
+    pub logfile: String,
+    pub log_retention: LogRetention,
+    pub log_level: LogLevel,
+    #[serde(deserialize_with = "deserialize_option_umask")]
+    pub umask: Option<u32>,
+    pub user: Option<String>,
+    pub group: Option<String>,
+    pub http_logging: Option<String>,
+    pub child_log_dir: String,
+    pub cgroup_root: String,
+}
+
+fn default_child_log_dir() -> String {
+    match unsafe { libc::geteuid() } {
+        0 => DEFAULT_CHILD_LOG_DIR.to_string(),
+        uid => format!("{USER_CHILD_LOG_DIR}-{uid}"),
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            socket: DEFAULT_SOCKET_PATH.to_string(),
+            socket_owner: None,
+            socket_group: None,
+            socket_mode: DEFAULT_SOCKET_MODE,
+            pidfile: DEFAULT_PID_FILE_PATH.to_string(),
+            logfile: DEFAULT_LOG_FILE_PATH.to_string(),
+            log_retention: LogRetention::Remove,
+            log_level: LogLevel::Debug,
+            umask: None,
+            user: None,
+            group: None,
+            http_logging: None,
+            child_log_dir: default_child_log_dir(),
+            cgroup_root: DEFAULT_CGROUP_ROOT.to_string(),
+        }
+    }
+}
+
+pub fn deserialize_octal<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Octal {
+        Number(u64),
+        Text(String),
+    }
+
+    Ok(match Octal::deserialize(deserializer)? {
+        Octal::Number(number) => number.to_string(),
+        Octal::Text(text) => text,
+    })
+}
+
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = deserialize_octal(deserializer)?;
+    parse_mode(&value).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_option_umask<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = deserialize_octal(deserializer)?;
+    parse_umask(&value)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+fn parse_permissions(value: &str) -> Option<u32> {
+    let value = value.trim();
+    let digits = value.strip_prefix("0o").unwrap_or(value);
+    match u32::from_str_radix(digits, 8) {
+        Ok(bits) if bits & 0o777 == bits => Some(bits),
+        _ => None,
+    }
+}
+
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    parse_permissions(value).ok_or(format!("\"{value}\" is not a valid socket mode."))
+}
+
+pub fn parse_umask(value: &str) -> Result<u32, String> {
+    parse_permissions(value).ok_or(format!("\"{value}\" is not a valid umask."))
+}
+
+pub fn parse_log_retention(value: &str) -> Result<LogRetention, String> {
+    match value {
+        "remove" => Ok(LogRetention::Remove),
+        "truncate" => Ok(LogRetention::Truncate),
+        "append" => Ok(LogRetention::Append),
+        _ => Err(format!(
+            "\"{value}\" is not a valid log retention, expected remove, truncate or append"
+        )),
+    }
+}
+
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
+
+pub fn installed_settings() -> Option<&'static Settings> {
+    SETTINGS.get()
+}
+
+impl Settings {
+    pub fn from_yml(path: &str) -> Result<Settings, String> {
+        let mut file = File::open(path).map_err(|err| format!("{}: {}", path, err))?;
+        let mut content = String::new();
+        file.read_to_string(&mut content)
+            .map_err(|err| format!("Can't read the file: {err}"))?;
+        let document: serde_yaml::Mapping =
+            serde_yaml::from_str(&content).map_err(|err| err.to_string())?;
+        let mut diagnostics = Diagnostics::new(&content);
+        let settings = match document.get(DAEMON_SECTION) {
+            None => Some(Settings::default()),
+            Some(section) => {
+                diagnostics.parse_section(DAEMON_SECTION, &[DAEMON_SECTION], section.clone())
+            }
+        };
+        diagnostics
+            .into_result(settings)
+            .map(|settings| settings.unwrap_or_default())
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.socket_ownership()?;
+        if let Some(user) = &self.user {
+            lookup_user(user).map_err(|e| format!("user: {e}"))?;
+        }
+        if let Some(group) = &self.group {
+            lookup_group(group).map_err(|e| format!("group: {e}"))?;
+        }
+        Ok(())
+    }
+
+    pub fn install(self) {
+        let _ = SETTINGS.set(self);
+    }
+
+    fn socket_ownership(&self) -> Result<(Option<uid_t>, Option<gid_t>), String> {
+        let uid = match &self.socket_owner {
+            Some(user) => Some(
+                lookup_user(user)
+                    .map_err(|e| format!("socket_owner: {e}"))?
+                    .0,
+            ),
+            None => None,
+        };
+        let gid = match &self.socket_group {
+            Some(group) => Some(lookup_group(group).map_err(|e| format!("socket_group: {e}"))?),
+            None => None,
+        };
+        Ok((uid, gid))
+    }
+
+    pub fn secure_socket(&self) -> Result<(), String> {
+        let (uid, gid) = self.socket_ownership()?;
+        if uid.is_some() || gid.is_some() {
+            let path = CString::new(self.socket.as_str())
+                .map_err(|_| format!("\"{}\" contains a nul byte", self.socket))?;
+            let ret = unsafe {
+                libc::chown(
+                    path.as_ptr(),
+                    uid.unwrap_or(uid_t::MAX),
+                    gid.unwrap_or(gid_t::MAX),
+                )
+            };
+            if ret == -1 {
+                return Err(format!(
+                    "Can't change owner of \"{}\": {}",
+                    self.socket,
+                    io::Error::last_os_error()
+                ));
+            }
+        }
+        fs::set_permissions(&self.socket, fs::Permissions::from_mode(self.socket_mode))
+            .map_err(|e| format!("Can't change permissions of \"{}\": {e}", self.socket))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_octal_permissions() {
+        assert_eq!(parse_mode("660"), Ok(0o660));
+        assert_eq!(parse_mode(" 0o600 "), Ok(0o600));
+        assert_eq!(parse_umask("022"), Ok(0o022));
+        assert!(parse_mode("1777").is_err());
+        assert!(parse_umask("089").is_err());
+        assert!(parse_umask("").is_err());
+    }
+
+    #[test]
+    fn accepts_numbers_and_strings() {
+        let numbers: Settings = serde_yaml::from_str("socket_mode: 660\numask: 027").unwrap();
+        let strings: Settings =
+            serde_yaml::from_str("socket_mode: \"660\"\numask: \"027\"").unwrap();
+        assert_eq!(numbers, strings);
+        assert_eq!(numbers.socket_mode, 0o660);
+        assert_eq!(numbers.umask, Some(0o027));
+    }
+
+    #[test]
+    fn rejects_invalid_modes() {
+        assert!(serde_yaml::from_str::<Settings>("socket_mode: 999").is_err());
+        assert!(serde_yaml::from_str::<Settings>("umask: \"abc\"").is_err());
+    }
+}
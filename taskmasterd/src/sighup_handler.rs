@@ -1,5 +1,6 @@
 use crate::action::Action;
-use crate::{remove_and_exit, UNIX_DOMAIN_SOCKET_PATH};
+use crate::remove_and_exit;
+use crate::settings::settings;
 use libc::{sighandler_t, signal, SIGHUP};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
@@ -14,7 +15,7 @@ fn send_update_message() -> Result<(), Box<dyn std::error::Error>> {
     use std::io::prelude::*;
     use std::os::unix::net::UnixStream;
 
-    let mut stream = UnixStream::connect(UNIX_DOMAIN_SOCKET_PATH)?;
+    let mut stream = UnixStream::connect(&settings().socket)?;
     let serialized_action = serde_json::to_string(&Action::Update(None))?;
     stream.write_all(serialized_action.as_bytes())?;
     Ok(())
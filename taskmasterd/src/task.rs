@@ -1,66 +1,272 @@
 extern crate libc;
 
 use crate::action::OutputType;
+use crate::cgroup::Cgroup;
 use crate::configuration::State::*;
-use crate::configuration::{Configuration, State};
-use crate::utils::open_file;
-use libc::{mode_t, pid_t};
+use crate::configuration::{is_auto_log, Configuration, State};
+use crate::events::Events;
+use crate::exit::ExitRecord;
+use crate::health::Health;
+use crate::identity::{user_name, Identity};
+use crate::log_file::{self, LogFile};
+use crate::output::{self, Decoration, LogMessages, OutputBuffer, Sink};
+use crate::report::{ErrorKind, ProcessResult};
+use crate::rlimit::{Resource, Rlimit};
+use crate::settings::LogLevel;
+use crate::utils::{earliest, get_effective_uid, open_file, random_below};
+use libc::{gid_t, mode_t, pid_t, uid_t};
+use std::collections::VecDeque;
 use std::fmt::{Display, Formatter};
-use std::fs::{File, OpenOptions};
-use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::fs::{DirBuilder, File, OpenOptions, Permissions};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
 use std::os::unix::process::CommandExt;
-use std::process::{Child, Command, Stdio};
-use std::time::SystemTime;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+const MANAGED_LOG_DIR_MODE: u32 = 0o755;
+const MANAGED_LOG_FILE_MODE: u32 = 0o640;
 
 pub struct Task {
+    pub name: String,
+    pub index: usize,
+    pub definition: Configuration,
     pub configuration: Configuration,
     pub state: State,
     pub restarts_left: u32,
+    pub restart_times: VecDeque<SystemTime>,
     pub child: Option<Child>,
     pub exit_code: Option<i32>,
+    pub exit_records: VecDeque<ExitRecord>,
     pub is_manual_restarting: bool,
+    pub waiting_for: Vec<String>,
+    pub stopping_after: Vec<String>,
+    pub cgroup: Option<Cgroup>,
+    pub health: Option<Health>,
+    pub log_messages: LogMessages,
+    pub stdout_buffer: Arc<Mutex<OutputBuffer>>,
+    pub stderr_buffer: Arc<Mutex<OutputBuffer>>,
 }
 
 impl Task {
-    pub fn new(configuration: &Configuration) -> Task {
+    pub fn new(definition: &Configuration, name: &str, index: usize, events: &Arc<Events>) -> Task {
+        let (configuration, state) = match definition.expand(name, index) {
+            Ok(configuration) => (configuration, STOPPED(None)),
+            Err(err) => (definition.clone(), FATAL(format!("Interpolation: {err}"))),
+        };
+        let buffer_lines = configuration.log_buffer_lines;
         Task {
+            name: name.to_string(),
+            index,
             is_manual_restarting: false,
-            restarts_left: configuration.start_retries,
-            configuration: configuration.clone(),
-            state: STOPPED(None),
+            waiting_for: Vec::new(),
+            stopping_after: Vec::new(),
+            restarts_left: definition.start_retries,
+            restart_times: VecDeque::new(),
+            definition: definition.clone(),
+            configuration,
+            state,
             exit_code: None,
+            exit_records: VecDeque::new(),
             child: None,
+            cgroup: None,
+            health: None,
+            log_messages: LogMessages::new(events.clone()),
+            stdout_buffer: Arc::new(Mutex::new(OutputBuffer::new(buffer_lines))),
+            stderr_buffer: Arc::new(Mutex::new(OutputBuffer::new(buffer_lines))),
+        }
+    }
+
+    fn open_managed_log(&self, path: &str) -> Result<File, String> {
+        if let Some(directory) = Path::new(path).parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(MANAGED_LOG_DIR_MODE)
+                .create(directory)
+                .map_err(|e| format!("{}: {e}", directory.display()))?;
+            log_file::check_private_directory(directory)?;
+        }
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(MANAGED_LOG_FILE_MODE)
+            .custom_flags(libc::O_NOFOLLOW)
+            .open(path)
+            .map_err(|e| format!("{path}: {e}"))?;
+        if !file.metadata().is_ok_and(|metadata| metadata.is_file()) {
+            return Err(format!("{path}: not a regular file"));
+        }
+        let identity = Identity::resolve(&self.configuration)?;
+        if identity.uid.is_some() || identity.gid.is_some() {
+            let ret = unsafe {
+                libc::fchown(
+                    file.as_raw_fd(),
+                    identity.uid.unwrap_or(uid_t::MAX),
+                    identity.gid.unwrap_or(gid_t::MAX),
+                )
+            };
+            if ret == -1 {
+                return Err(format!(
+                    "Can't change owner of {path}: {}",
+                    io::Error::last_os_error()
+                ));
+            }
+        }
+        file.set_permissions(Permissions::from_mode(MANAGED_LOG_FILE_MODE))
+            .map_err(|e| format!("Can't change permissions of {path}: {e}"))?;
+        Ok(file)
+    }
+
+    pub fn output_buffer(&self, output_type: &OutputType) -> &Arc<Mutex<OutputBuffer>> {
+        match output_type {
+            OutputType::Stdout => &self.stdout_buffer,
+            OutputType::Stderr => &self.stderr_buffer,
+        }
+    }
+
+    fn setup_stream(&self, output_type: OutputType) -> Result<Option<Vec<Sink>>, String> {
+        let (stream_type, definition) = match output_type {
+            OutputType::Stdout => (&self.configuration.stdout, &self.definition.stdout),
+            OutputType::Stderr => (&self.configuration.stderr, &self.definition.stderr),
+        };
+        if (output_type == OutputType::Stderr && self.configuration.redirect_stderr)
+            || !self.configuration.captures_output(stream_type)
+        {
+            return Ok(None);
+        }
+        let mut sinks = vec![Sink::Buffer(self.output_buffer(&output_type).clone())];
+        if let Some(path) = stream_type {
+            let file = if is_auto_log(definition) {
+                self.open_managed_log(path)?
+            } else {
+                open_file(path)?
+            };
+            sinks.push(Sink::File(LogFile::new(
+                path,
+                file,
+                self.configuration.logfile_maxbytes,
+                self.configuration.logfile_backups,
+                self.configuration.logfile_compress,
+            )));
         }
+        if self.configuration.log_syslog {
+            sinks.push(Sink::syslog(output_type == OutputType::Stderr));
+        }
+        if let Some(target) = &self.configuration.log_http {
+            sinks.push(Sink::http(target));
+        }
+        Ok(Some(sinks))
     }
 
-    fn setup_stream(&self, stream_type: &Option<String>) -> Result<Stdio, String> {
-        match stream_type {
-            Some(path) => open_file(path).map(|file| file.into()),
-            None => Ok(Stdio::null()),
+    fn output_stdio(sinks: &Option<Vec<Sink>>) -> Stdio {
+        match sinks {
+            Some(_) => Stdio::piped(),
+            None => Stdio::null(),
         }
     }
 
-    unsafe fn setup_child_process(&mut self, stderr: Stdio, stdout: Stdio) -> Result<(), String> {
+    fn label(&self) -> String {
+        format!("{}:{}", self.name, self.index)
+    }
+
+    fn decoration(&self) -> Decoration {
+        Decoration {
+            timestamps: self.configuration.log_timestamps,
+            prefix: self.configuration.log_prefix.then(|| self.label()),
+        }
+    }
+
+    unsafe fn setup_child_process(
+        &mut self,
+        stderr: Option<Vec<Sink>>,
+        stdout: Option<Vec<Sink>>,
+    ) -> Result<(), String> {
         let umask_val = self.configuration.umask as mode_t;
-        let args: Vec<_> = self.configuration.cmd.split_whitespace().collect();
-        match Command::new(args[0])
+        let args = self.configuration.command_line().map_err(|err| {
+            let err_msg = format!("Command: {err}");
+            self.state = FATAL(err_msg.clone());
+            err_msg
+        })?;
+        let identity = Identity::resolve(&self.configuration).map_err(|err| {
+            let err_msg = format!("Identity: {err}");
+            self.state = FATAL(err_msg.clone());
+            err_msg
+        })?;
+        let cgroup_procs = match &self.cgroup {
+            Some(cgroup) => Some(
+                cgroup
+                    .setup(&self.configuration)
+                    .and_then(|_| cgroup.open_procs())
+                    .map_err(|err| {
+                        let err_msg = format!("Cgroup: {err}");
+                        self.state = FATAL(err_msg.clone());
+                        err_msg
+                    })?,
+            ),
+            None => None,
+        };
+        let cgroup_procs_fd = cgroup_procs.as_ref().map(|file| file.as_raw_fd());
+        let redirect_stderr = self.configuration.redirect_stderr;
+        let rlimits: Vec<(Resource, Rlimit)> = self
+            .configuration
+            .rlimits
+            .iter()
+            .map(|(resource, rlimit)| (*resource, *rlimit))
+            .collect();
+        match Command::new(&args[0])
             .args(&args[1..])
             .current_dir(match &self.configuration.working_dir {
                 Some(cwd) => &cwd,
                 None => ".",
             })
             .envs(&self.configuration.env)
-            .stdout(stdout)
-            .stderr(stderr)
+            .stdout(Self::output_stdio(&stdout))
+            .stderr(Self::output_stdio(&stderr))
             .pre_exec(move || {
                 unsafe {
+                    if libc::setpgid(0, 0) == -1 {
+                        return Err(io::Error::last_os_error());
+                    }
                     libc::umask(umask_val);
+                    if redirect_stderr && libc::dup2(libc::STDOUT_FILENO, libc::STDERR_FILENO) == -1
+                    {
+                        return Err(io::Error::last_os_error());
+                    }
+                    if let Some(fd) = cgroup_procs_fd {
+                        if libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1) == -1 {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
                 }
-                Ok(())
+                for (resource, rlimit) in &rlimits {
+                    rlimit.apply(*resource)?;
+                }
+                identity.apply()
             })
             .spawn()
         {
-            Ok(child) => {
+            Ok(mut child) => {
+                if let (Some(sinks), Some(output)) = (stdout, child.stdout.take()) {
+                    output::spawn_reader(
+                        self.label(),
+                        output,
+                        self.decoration(),
+                        sinks,
+                        self.log_messages.clone(),
+                    );
+                }
+                if let (Some(sinks), Some(output)) = (stderr, child.stderr.take()) {
+                    output::spawn_reader(
+                        self.label(),
+                        output,
+                        self.decoration(),
+                        sinks,
+                        self.log_messages.clone(),
+                    );
+                }
                 self.child = Some(child);
                 Ok(())
             }
@@ -74,12 +280,13 @@ impl Task {
 
     pub fn run(&mut self) -> Result<(), String> {
         self.state = STARTING(SystemTime::now());
-        let stderr = self.setup_stream(&self.configuration.stderr).map_err(|e| {
+        self.health = self.configuration.health_check.clone().map(Health::new);
+        let stderr = self.setup_stream(OutputType::Stderr).map_err(|e| {
             let error_msg = format!("Stderr log file: {}", e);
             self.state = FATAL(error_msg.clone());
             error_msg
         })?;
-        let stdout = self.setup_stream(&self.configuration.stdout).map_err(|e| {
+        let stdout = self.setup_stream(OutputType::Stdout).map_err(|e| {
             let error_msg = format!("Stdout log file: {}", e);
             self.state = FATAL(error_msg.clone());
             error_msg
@@ -92,60 +299,219 @@ impl Task {
         Ok(())
     }
 
-    pub fn kill(&mut self) -> Result<(), String> {
-        return match &mut self.child {
-            None => Err(format!(
-                "Can't find child process, probably was already stopped or not started"
-            )),
-            Some(child) => {
-                if let Err(error) = child.kill() {
-                    return Err(format!("Can't kill child process, {error}"));
-                }
-                self.state = STOPPED(Some(SystemTime::now()));
-                self.child = None;
-                Ok(())
+    fn send_kill_signal(&mut self) -> Result<(), String> {
+        let Some(child) = &mut self.child else {
+            return Err(
+                "Can't find child process, probably was already stopped or not started".to_string(),
+            );
+        };
+        if self.configuration.killasgroup || self.configuration.stopasgroup {
+            if unsafe { libc::kill(-(child.id() as pid_t), libc::SIGKILL) } == -1 {
+                return Err(format!(
+                    "Can't kill child process group, {}",
+                    io::Error::last_os_error()
+                ));
             }
+        } else if let Err(error) = child.kill() {
+            return Err(format!("Can't kill child process, {error}"));
+        }
+        Ok(())
+    }
+
+    pub fn kill(&mut self) -> Result<(), String> {
+        self.send_kill_signal()?;
+        if let Some(mut child) = self.child.take() {
+            let _ = child.wait();
+        }
+        self.state = STOPPED(Some(SystemTime::now()));
+        Ok(())
+    }
+
+    fn send_stop_signal(&self) -> Result<(), String> {
+        let Some(child) = &self.child else {
+            return Err(
+                "Can't find child process, probably was already stopped or not started".to_string(),
+            );
+        };
+        let pid = if self.configuration.stopasgroup {
+            -(child.id() as pid_t)
+        } else {
+            child.id() as pid_t
         };
+        unsafe {
+            libc::kill(pid, self.configuration.stop_signal.clone().into());
+        }
+        Ok(())
     }
 
     pub fn stop(&mut self) -> Result<(), String> {
-        return match &mut self.child {
-            None => Err(format!(
-                "Can't find child process, probably was already stopped or not started"
-            )),
-            Some(child) => {
-                unsafe {
-                    libc::kill(
-                        child.id() as pid_t,
-                        self.configuration.stop_signal.clone().into(),
-                    );
-                }
-                self.state = STOPPING(SystemTime::now());
-                Ok(())
-            }
-        };
+        self.stopping_after.clear();
+        self.send_stop_signal()?;
+        self.state = STOPPING(SystemTime::now());
+        Ok(())
+    }
+
+    pub fn check_health(&mut self, events: &Arc<Events>) -> Option<Result<(), String>> {
+        let health = self.health.as_mut()?;
+        health.poll(&self.configuration, events)
+    }
+
+    pub fn health_error(&self) -> Option<String> {
+        self.health.as_ref()?.last_error.clone()
+    }
+
+    pub fn is_restarting_unhealthy(&self) -> bool {
+        self.health
+            .as_ref()
+            .is_some_and(|health| health.stopped_at.is_some())
     }
 
-    pub fn signal(&self, signum: u8, task_name: &str, idx: usize) -> String {
+    pub fn restart_unhealthy(&mut self) -> Result<(), String> {
+        self.send_stop_signal()?;
+        if let Some(health) = &mut self.health {
+            health.stopped_at = Some(SystemTime::now());
+        }
+        Ok(())
+    }
+
+    pub fn kill_unhealthy(&mut self) -> Result<ExitStatus, String> {
+        self.send_kill_signal()?;
+        match &mut self.child {
+            Some(child) => child
+                .wait()
+                .map_err(|e| format!("Can't wait for child process, {e}")),
+            None => Err("Can't find child process".to_string()),
+        }
+    }
+
+    pub fn pid(&self) -> Option<pid_t> {
+        self.child.as_ref().map(|child| child.id() as pid_t)
+    }
+
+    pub fn cancel_waiting(&mut self) {
+        self.waiting_for.clear();
+        if self.state == STOPPED(None) {
+            self.state = STOPPED(Some(SystemTime::now()));
+        }
+    }
+
+    pub fn kill_leftover_group(&self, pgid: u32) -> bool {
+        if !self.configuration.killasgroup && !self.configuration.stopasgroup {
+            return false;
+        }
+        unsafe { libc::kill(-(pgid as pid_t), libc::SIGKILL) == 0 }
+    }
+
+    pub fn signal(&self, signum: u8, task_name: &str, idx: usize) -> ProcessResult {
         match &self.child {
-            None => format!(
-                "Failed to send signal {signum} to {task_name}[{idx}] because it is not running\n"
+            None => ProcessResult::error(
+                task_name,
+                idx,
+                ErrorKind::InvalidState,
+                format!("Failed to send signal {signum} because it is not running"),
             ),
-            Some(child) => {
-                println!("{}", child.id() as pid_t);
-                unsafe {
-                    if libc::kill(child.id() as pid_t, signum as i32) == -1 {
-                        format!("{task_name}[{idx}] did not receive signal {signum}\n")
-                    } else {
-                        format!("{task_name}[{idx}] received signal {signum}\n")
-                    }
+            Some(child) => unsafe {
+                if libc::kill(child.id() as pid_t, signum as i32) == -1 {
+                    ProcessResult::error(
+                        task_name,
+                        idx,
+                        ErrorKind::SignalFailed,
+                        format!(
+                            "did not receive signal {signum}: {}",
+                            io::Error::last_os_error()
+                        ),
+                    )
+                } else {
+                    ProcessResult::ok(task_name, idx, format!("received signal {signum}"))
                 }
+            },
+        }
+    }
+
+    pub fn next_deadline(&self) -> Option<SystemTime> {
+        match self.state {
+            STARTING(started_at) => {
+                let started = started_at + Duration::from_secs(self.configuration.start_time);
+                match &self.health {
+                    Some(health) if started <= SystemTime::now() => health.next_deadline(),
+                    _ => Some(started),
+                }
+            }
+            RUNNING(started_at) | UNHEALTHY(started_at) => {
+                let reset = (matches!(self.state, RUNNING(_))
+                    && self.restarts_left < self.configuration.start_retries)
+                    .then(|| started_at + Duration::from_secs(self.configuration.backoff_reset));
+                let health = self
+                    .health
+                    .as_ref()
+                    .and_then(|health| match health.stopped_at {
+                        Some(stopped_at) => {
+                            Some(stopped_at + Duration::from_secs(self.configuration.stop_time))
+                        }
+                        None => health.next_deadline(),
+                    });
+                earliest(reset, health)
+            }
+            BACKOFF(until) => Some(until),
+            STOPPING(stopped_at) => {
+                Some(stopped_at + Duration::from_secs(self.configuration.stop_time))
             }
+            STOPPED(_) if self.is_manual_restarting => Some(SystemTime::now()),
+            _ => None,
         }
     }
 
-    pub fn get_json_configuration(&self) -> String {
-        serde_json::to_string_pretty(&self.configuration).expect("Serialization failed")
+    pub fn record_exit(&mut self, status: &ExitStatus) -> ExitRecord {
+        let record = ExitRecord::new(
+            status,
+            &self.configuration.exit_codes,
+            &self.configuration.exit_signals,
+        );
+        while self.exit_records.len() >= self.configuration.exit_history as usize {
+            self.exit_records.pop_front();
+        }
+        self.exit_records.push_back(record.clone());
+        self.exit_code = status.code();
+        record
+    }
+
+    pub fn record_restart(&mut self) -> Result<(), String> {
+        let Some(max_restarts) = self.configuration.max_restarts_per_window else {
+            return Ok(());
+        };
+        let now = SystemTime::now();
+        let window = Duration::from_secs(self.configuration.restart_window);
+        self.restart_times.retain(|restarted_at| {
+            now.duration_since(*restarted_at)
+                .unwrap_or(Duration::from_secs(0))
+                < window
+        });
+        if self.restart_times.len() >= max_restarts as usize {
+            return Err(format!(
+                "restarted {max_restarts} times in {}s",
+                self.configuration.restart_window
+            ));
+        }
+        self.restart_times.push_back(now);
+        Ok(())
+    }
+
+    pub fn backoff_delay(&self) -> Duration {
+        let attempt = self
+            .configuration
+            .start_retries
+            .saturating_sub(self.restarts_left);
+        let max = self.configuration.backoff_max.saturating_mul(1000);
+        let mut delay = self.configuration.backoff_initial.saturating_mul(1000);
+        for _ in 0..attempt {
+            if delay >= max {
+                break;
+            }
+            delay = delay.saturating_mul(self.configuration.backoff_multiplier as u64);
+        }
+        let delay = delay.min(max);
+        let jitter = random_below(delay * self.configuration.backoff_jitter as u64 / 100 + 1);
+        Duration::from_millis(delay + jitter)
     }
 
     pub fn can_be_launched(&self) -> bool {
@@ -161,18 +527,13 @@ impl Task {
         file_name: Option<String>,
         output_type: OutputType,
     ) -> String {
+        self.output_buffer(&output_type).lock().unwrap().clear();
         match file_name {
             None => format!("{task_name} does not have a {output_type} log file\n"),
-            Some(file_name) => match File::create(&file_name) {
-                Ok(file) => {
-                    if let Err(e) = file.set_len(0) {
-                        format!("Failed to clear {output_type} log file for {task_name}: {e}\n")
-                    } else {
-                        format!("Cleared {output_type} log file for {task_name}\n")
-                    }
-                }
+            Some(file_name) => match log_file::clear(&file_name) {
+                Ok(_) => format!("Cleared {output_type} log file for {task_name}\n"),
                 Err(e) => {
-                    format!("Failed to open {output_type} log file for {task_name}: {e}\n")
+                    format!("Failed to clear {output_type} log file for {task_name}: {e}\n")
                 }
             },
         }
@@ -190,41 +551,8 @@ impl Task {
         )
     }
 
-    pub fn truncate_file(filename: &str, size: u64) -> io::Result<()> {
-        let file = OpenOptions::new().read(true).write(true).open(filename)?;
-        let mut file = io::BufReader::new(file);
-        let mut contents = Vec::new();
-        let _ = file.seek(SeekFrom::Start(size / 2))?;
-        file.read_to_end(&mut contents)?;
-        drop(file);
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(filename)?;
-        file.write_all(b"[TRUNCATED]\n")?;
-        file.write_all(&contents)?;
-        Ok(())
-    }
-
-    fn control_log_file_limit(filename: &Option<String>, max_size: u64, logs: &mut Vec<String>) {
-        if let Some(filename) = filename {
-            if let Ok(metadata) = std::fs::metadata(&filename) {
-                let size = metadata.len();
-                if size > max_size {
-                    logs.push(match Self::truncate_file(&filename, size) {
-                        Ok(_) => format!("{filename} was truncated from {size} bytes."),
-                        Err(err) => format!("Failed to truncate {filename}: {err}"),
-                    });
-                }
-            }
-        }
-    }
-
-    pub fn control_log_files_limit(&self, max_size: u64) -> Vec<String> {
-        let mut logs = Vec::new();
-        Self::control_log_file_limit(&self.configuration.stdout, max_size, &mut logs);
-        Self::control_log_file_limit(&self.configuration.stderr, max_size, &mut logs);
-        logs
+    pub fn take_log_messages(&self) -> Vec<(LogLevel, String)> {
+        self.log_messages.take()
     }
 }
 
@@ -233,19 +561,103 @@ impl Display for Task {
         let mut result = self.state.to_string();
         match self.state {
             STOPPING(_) => {}
+            STOPPED(_) if !self.waiting_for.is_empty() => {
+                result += &format!(" (waiting for {})", self.waiting_for.join(", "))
+            }
             STOPPED(_) => {}
             STARTING(_) => {}
-            RUNNING(_) => {
+            RUNNING(_) | UNHEALTHY(_) => {
                 let pid = match self.child.as_ref() {
                     None => 0,
                     Some(child) => child.id(),
                 };
-                result += &format!(" (PID {})", pid)
+                result += &match get_effective_uid(pid) {
+                    Some(uid) => match user_name(uid) {
+                        Some(name) => format!(" (PID {pid}, UID {uid} {name})"),
+                        None => format!(" (PID {pid}, UID {uid})"),
+                    },
+                    None => format!(" (PID {})", pid),
+                };
+                if let (UNHEALTHY(_), Some(error)) = (&self.state, self.health_error()) {
+                    result += &format!(" ({error})");
+                }
+                if !self.stopping_after.is_empty() {
+                    result += &format!(" (stopping after {})", self.stopping_after.join(", "));
+                }
             }
-            BACKOFF => result += " (Exited too quickly)",
-            EXITED(_) => {}
-            FATAL(_) => {}
+            BACKOFF(_) if self.is_restarting_unhealthy() => result += " (Unhealthy)",
+            BACKOFF(_) => result += " (Exited too quickly)",
+            EXITED(_) | FATAL(_) => {}
         };
+        if let (BACKOFF(_) | EXITED(_) | FATAL(_), Some(record)) =
+            (&self.state, self.exit_records.back())
+        {
+            result += &format!(" (last exit: {record})");
+        }
         write!(f, "{result}")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(configuration: Configuration) -> Task {
+        Task::new(&configuration, "web", 0, &Arc::new(Events::new()))
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let mut task = task(Configuration {
+            start_retries: 5,
+            backoff_initial: 1,
+            backoff_multiplier: 2,
+            backoff_max: 8,
+            backoff_jitter: 0,
+            ..Configuration::default()
+        });
+        let mut delays = Vec::new();
+        for restarts_left in (0..=5).rev() {
+            task.restarts_left = restarts_left;
+            delays.push(task.backoff_delay().as_secs());
+        }
+        assert_eq!(delays, [1, 2, 4, 8, 8, 8]);
+    }
+
+    #[test]
+    fn limits_restarts_per_window() {
+        let mut unlimited = task(Configuration::default());
+        for _ in 0..100 {
+            assert_eq!(unlimited.record_restart(), Ok(()));
+        }
+        let mut task = task(Configuration {
+            max_restarts_per_window: Some(2),
+            restart_window: 60,
+            ..Configuration::default()
+        });
+        assert_eq!(task.record_restart(), Ok(()));
+        assert_eq!(task.record_restart(), Ok(()));
+        assert_eq!(
+            task.record_restart(),
+            Err("restarted 2 times in 60s".to_string())
+        );
+        for restarted_at in task.restart_times.iter_mut() {
+            *restarted_at -= Duration::from_secs(61);
+        }
+        assert_eq!(task.record_restart(), Ok(()));
+        assert_eq!(task.restart_times.len(), 1);
+    }
+
+    #[test]
+    fn adds_bounded_jitter() {
+        let task = task(Configuration {
+            backoff_initial: 2,
+            backoff_jitter: 50,
+            ..Configuration::default()
+        });
+        for _ in 0..100 {
+            let delay = task.backoff_delay();
+            assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(3));
+        }
+    }
+}
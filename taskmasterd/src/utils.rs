@@ -1,8 +1,18 @@
 use std::{
-    fs::{File, OpenOptions},
+    collections::hash_map::RandomState,
+    fs::{self, File, OpenOptions},
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{TcpStream, ToSocketAddrs},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    os::unix::net::UnixStream,
+    path::{Component, Path, PathBuf},
+    thread,
     time::{Duration, SystemTime},
 };
 
+const CONNECT_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
 pub fn is_time_elapsed(started_at: SystemTime, duration: u64) -> bool {
     let current_time = SystemTime::now();
     let elapsed_time = current_time
@@ -11,6 +21,85 @@ pub fn is_time_elapsed(started_at: SystemTime, duration: u64) -> bool {
     elapsed_time.as_secs() >= duration
 }
 
+pub fn earliest(first: Option<SystemTime>, second: Option<SystemTime>) -> Option<SystemTime> {
+    match (first, second) {
+        (Some(first), Some(second)) => Some(first.min(second)),
+        (first, second) => first.or(second),
+    }
+}
+
+pub fn random_below(bound: u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    RandomState::new().build_hasher().finish() % bound
+}
+
+pub fn connect_tcp(address: &str, timeout: Duration) -> Result<TcpStream, String> {
+    let addresses = address
+        .to_socket_addrs()
+        .map_err(|e| format!("can't resolve {address}: {e}"))?;
+    let mut error = format!("can't resolve {address}");
+    for socket_address in addresses {
+        match TcpStream::connect_timeout(&socket_address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => error = format!("can't connect to {address}: {e}"),
+        }
+    }
+    Err(error)
+}
+
+pub fn connect_unix(path: &str, timeout: Duration) -> Result<UnixStream, String> {
+    let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+    address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    if path.len() >= address.sun_path.len() {
+        return Err(format!("can't connect to {path}: path is too long"));
+    }
+    for (dst, src) in address.sun_path.iter_mut().zip(path.as_bytes()) {
+        *dst = *src as libc::c_char;
+    }
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_UNIX,
+            libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+            0,
+        )
+    };
+    if fd < 0 {
+        return Err(format!(
+            "can't connect to {path}: {}",
+            io::Error::last_os_error()
+        ));
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let stream = UnixStream::from(fd);
+    let deadline = SystemTime::now() + timeout;
+    loop {
+        let result = unsafe {
+            libc::connect(
+                stream.as_raw_fd(),
+                &address as *const libc::sockaddr_un as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t,
+            )
+        };
+        if result == 0 {
+            break;
+        }
+        let error = io::Error::last_os_error();
+        match error.kind() {
+            io::ErrorKind::WouldBlock if SystemTime::now() < deadline => {
+                thread::sleep(CONNECT_RETRY_INTERVAL)
+            }
+            io::ErrorKind::WouldBlock => return Err(format!("can't connect to {path}: timed out")),
+            _ => return Err(format!("can't connect to {path}: {error}")),
+        }
+    }
+    stream
+        .set_nonblocking(false)
+        .map_err(|e| format!("can't connect to {path}: {e}"))?;
+    Ok(stream)
+}
+
 pub fn open_file(path: &String) -> Result<File, String> {
     OpenOptions::new()
         .append(true)
@@ -18,3 +107,132 @@ pub fn open_file(path: &String) -> Result<File, String> {
         .open(path)
         .map_err(|e| e.to_string())
 }
+
+pub fn get_effective_uid(pid: u32) -> Option<u32> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    status
+        .lines()
+        .find(|line| line.starts_with("Uid:"))?
+        .split_whitespace()
+        .nth(2)?
+        .parse()
+        .ok()
+}
+
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub fn glob(pattern: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut candidates = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let part = component.as_os_str().to_string_lossy();
+        let is_pattern = matches!(component, Component::Normal(_)) && part.contains(['*', '?']);
+        if !is_pattern {
+            candidates.iter_mut().for_each(|path| path.push(component));
+            continue;
+        }
+        let mut matches = Vec::new();
+        for directory in &candidates {
+            let directory = if directory.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                directory.as_path()
+            };
+            let Ok(entries) = fs::read_dir(directory) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if (!name.starts_with('.') || part.starts_with('.')) && wildcard_match(&part, &name)
+                {
+                    matches.push(entry.path());
+                }
+            }
+        }
+        candidates = matches;
+    }
+    if !pattern.to_string_lossy().contains(['*', '?']) && !pattern.exists() {
+        return Err(format!("{}: No such file or directory", pattern.display()));
+    }
+    candidates.retain(|path| path.is_file());
+    candidates.sort();
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("web-?", "web-1"));
+        assert!(wildcard_match("*-admin*", "ops-admin-2"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("web-?", "web-10"));
+        assert!(!wildcard_match("a*b", "acbd"));
+        assert!(!wildcard_match("", "x"));
+    }
+
+    #[test]
+    fn globs_files() {
+        let base = std::env::temp_dir().join(format!("taskmaster-glob-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        for file in [
+            "conf.d/b.yml",
+            "conf.d/a.yml",
+            "conf.d/.hidden.yml",
+            "conf.d/c.txt",
+        ] {
+            fs::create_dir_all(base.join(file).parent().unwrap()).unwrap();
+            fs::write(base.join(file), "").unwrap();
+        }
+        fs::create_dir_all(base.join("conf.d/dir.yml")).unwrap();
+        let found = glob(&base.join("conf.d/*.yml"));
+        let missing = glob(&base.join("conf.d/missing.yml"));
+        let empty = glob(&base.join("*/none-*.yml"));
+        fs::remove_dir_all(&base).unwrap();
+        assert_eq!(
+            found.unwrap(),
+            [base.join("conf.d/a.yml"), base.join("conf.d/b.yml")]
+        );
+        assert!(missing.is_err());
+        assert_eq!(empty, Ok(Vec::new()));
+    }
+
+    #[test]
+    fn connects_to_unix_sockets() {
+        let path =
+            std::env::temp_dir().join(format!("taskmaster-unix-{}.sock", std::process::id()));
+        let path = path.display().to_string();
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        assert!(connect_unix(&path, Duration::from_secs(1)).is_ok());
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+        assert!(connect_unix(&path, Duration::from_secs(1))
+            .unwrap_err()
+            .starts_with(&format!("can't connect to {path}: ")));
+    }
+}